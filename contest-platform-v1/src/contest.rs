@@ -5,11 +5,11 @@ use serde_with::DeserializeFromStr;
 use thiserror::Error;
 use time::Duration;
 
-use crate::judge::ResourceLimits;
+use crate::judge::{Comparison, ResourceLimits};
 
 mod loader;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Contest {
     pub name: String,
     pub path: PathBuf,
@@ -19,9 +19,10 @@ pub struct Contest {
     pub cooldown: Duration,
     pub leaderboard_size: usize,
     pub rlimits: ContestResourceLimits,
+    pub comparison: Comparison,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Task {
     pub name: String,
     pub page: String,
@@ -30,6 +31,7 @@ pub struct Task {
     pub constraints: Vec<String>,
     pub tests: Vec<Test>,
     pub difficulty: Option<Difficulty>,
+    pub comparison: Option<Comparison>,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
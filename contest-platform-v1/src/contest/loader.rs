@@ -29,6 +29,8 @@ struct ContestFrontmatter {
     #[serde(default = "defaults::leaderboard_size")]
     leaderboard_size: usize,
     rlimits: ContestResourceLimits,
+    #[serde(default)]
+    comparison: Comparison,
 }
 
 impl Contest {
@@ -46,9 +48,8 @@ impl Contest {
         for task_path in frontmatter.task_paths {
             let path = path.join(task_path);
             if !path.is_dir() {
-                return Err(LoadContestError::Io(io::Error::new(
-                    io::ErrorKind::Other, // NotADirectory
-                    "task is not a directory",
+                return Err(LoadContestError::Io(io::Error::other(
+                    "task is not a directory", // NotADirectory
                 )));
             }
 
@@ -64,6 +65,7 @@ impl Contest {
             cooldown: frontmatter.cooldown,
             leaderboard_size: frontmatter.leaderboard_size,
             rlimits: frontmatter.rlimits,
+            comparison: frontmatter.comparison,
         })
     }
 }
@@ -79,6 +81,8 @@ struct TaskFrontmatter {
     constraints: Vec<String>,
     #[serde(default)]
     difficulty: Option<Difficulty>,
+    #[serde(default)]
+    comparison: Option<Comparison>,
 }
 
 impl Task {
@@ -124,6 +128,7 @@ impl Task {
             tests,
             constraints: frontmatter.constraints,
             difficulty: frontmatter.difficulty,
+            comparison: frontmatter.comparison,
         })
     }
 }
@@ -4,8 +4,9 @@ use axum_typed_multipart::TryFromMultipart;
 use serde::Deserialize;
 use thiserror::Error;
 
-pub use self::{grade::*, run::run, sandbox::*};
+pub use self::{compare::*, grade::*, run::run, sandbox::*};
 
+mod compare;
 mod grade;
 mod run;
 mod sandbox;
@@ -3,7 +3,7 @@ use std::{fmt, str::FromStr};
 use thiserror::Error;
 use yansi::Paint;
 
-use super::{run::TestResult, sandbox::ResourceUsage};
+use super::{compare::Mismatch, run::TestResult, sandbox::ResourceUsage};
 use crate::contest::Task;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -25,6 +25,7 @@ pub struct GradedTest {
     pub verdict: Verdict,
     pub score: u32,
     pub resource_usage: Option<ResourceUsage>,
+    pub mismatch: Option<Mismatch>,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -32,6 +33,7 @@ pub enum Verdict {
     WrongAnswer,
     TimeLimitExceeded,
     MemoryLimitExceeded,
+    OutputLimitExceeded,
     PartialScore,
     CompileError,
     RuntimeError,
@@ -42,7 +44,9 @@ impl Verdict {
     pub fn fmt_colored(&self) -> impl fmt::Display + '_ {
         let paint = match self {
             Verdict::WrongAnswer => Paint::red,
-            Verdict::TimeLimitExceeded | Verdict::MemoryLimitExceeded => Paint::magenta,
+            Verdict::TimeLimitExceeded
+            | Verdict::MemoryLimitExceeded
+            | Verdict::OutputLimitExceeded => Paint::magenta,
             Verdict::PartialScore => Paint::blue,
             Verdict::CompileError | Verdict::RuntimeError => Paint::yellow,
             Verdict::Accepted => Paint::green,
@@ -60,6 +64,7 @@ impl fmt::Display for Verdict {
             Verdict::WrongAnswer => "Wrong Answer",
             Verdict::TimeLimitExceeded => "Time Limit Exceeded",
             Verdict::MemoryLimitExceeded => "Memory Limit Exceeded",
+            Verdict::OutputLimitExceeded => "Output Limit Exceeded",
             Verdict::PartialScore => "Partial Score",
             Verdict::Accepted => "Accepted",
         }
@@ -81,6 +86,7 @@ impl FromStr for Verdict {
             "wrong answer" => Verdict::WrongAnswer,
             "time limit exceeded" => Verdict::TimeLimitExceeded,
             "memory limit exceeded" => Verdict::MemoryLimitExceeded,
+            "output limit exceeded" => Verdict::OutputLimitExceeded,
             "partial score" => Verdict::PartialScore,
             "accepted" => Verdict::Accepted,
             _ => return Err(InvalidVerdict(s.to_owned())),
@@ -107,7 +113,8 @@ pub fn grade(task: &Task, results: &[TestResult]) -> GradedTask {
         for TestResult {
             verdict,
             resource_usage,
-        } in iter.by_ref().copied().take(subtask.tests)
+            mismatch,
+        } in iter.by_ref().take(subtask.tests).cloned()
         {
             let score = if let Verdict::Accepted = verdict {
                 1
@@ -121,6 +128,7 @@ pub fn grade(task: &Task, results: &[TestResult]) -> GradedTask {
                 verdict,
                 score,
                 resource_usage,
+                mismatch,
             })
         }
 
@@ -1,3 +1,5 @@
+use std::os::unix::process::ExitStatusExt;
+
 use rayon::prelude::*;
 
 use super::*;
@@ -6,10 +8,11 @@ use crate::contest::{ContestResourceLimits, Task, Test};
 const MEMORY_USAGE_EPSILON: u64 = 1000;
 const TIME_ELAPSED_EPSILON: f64 = 0.1;
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct TestResult {
     pub verdict: Verdict,
     pub resource_usage: Option<ResourceUsage>,
+    pub mismatch: Option<Mismatch>,
 }
 
 #[tracing::instrument(skip(task), err)]
@@ -18,6 +21,7 @@ pub fn run(
     submission: Submission,
     task: &Task,
     rlimits: ContestResourceLimits,
+    comparison: Comparison,
 ) -> JudgeResult<Vec<TestResult>> {
     let Some(language) = config
         .languages
@@ -45,6 +49,7 @@ pub fn run(
                 &sandbox,
                 &language.run,
                 rlimits.run,
+                comparison,
                 test_case,
                 (idx + 1, task.tests.len()),
             )?;
@@ -83,11 +88,40 @@ fn build(sandbox: &Sandbox, command: &Command, rlimits: ResourceLimits) -> Judge
     }
 }
 
-#[tracing::instrument(skip(sandbox, command, rlimits, test, test_count), err)]
+/// The verdict for a process that was stopped by a limit, if it was stopped by one
+///
+/// The soft CPU time limit sends `SIGXCPU`, but the hard limit a second later sends `SIGKILL`,
+/// and running out of memory usually shows up as a crash (e.g. dereferencing a failed `malloc`)
+/// or an abort (e.g. an uncaught `std::bad_alloc`), so those are only put down to a limit if the
+/// usage agrees. Anything else killed by a signal is a runtime error.
+fn exceeded(rlimits: ResourceLimits, output: &Output) -> Option<Verdict> {
+    let usage = output.resource_usage;
+    let time_left = rlimits.cpu_seconds as f64 - usage.total_time().as_seconds_f64();
+    let memory_left = rlimits.memory_bytes.saturating_sub(usage.memory_bytes);
+
+    match output.exit_status.signal() {
+        // the output was cut off (possibly mid-character), so it isn't worth comparing
+        _ if output.output_limit_exceeded => Some(Verdict::OutputLimitExceeded),
+        Some(libc::SIGXFSZ) => Some(Verdict::OutputLimitExceeded),
+        Some(libc::SIGXCPU) => Some(Verdict::TimeLimitExceeded),
+        Some(libc::SIGKILL) if time_left <= TIME_ELAPSED_EPSILON => {
+            Some(Verdict::TimeLimitExceeded)
+        }
+        Some(libc::SIGKILL | libc::SIGSEGV | libc::SIGBUS | libc::SIGABRT)
+            if memory_left <= MEMORY_USAGE_EPSILON =>
+        {
+            Some(Verdict::MemoryLimitExceeded)
+        }
+        _ => None,
+    }
+}
+
+#[tracing::instrument(skip(sandbox, command, rlimits, comparison, test, test_count), err)]
 fn test(
     sandbox: &Sandbox,
     command: &Command,
     rlimits: ResourceLimits,
+    comparison: Comparison,
     test: &Test,
     (test_number, test_count): (usize, usize),
 ) -> JudgeResult<TestResult> {
@@ -96,29 +130,18 @@ fn test(
         Err(e) => return Err(JudgeError::Io(e)),
     };
 
-    let stdout = std::str::from_utf8(&output.stdout)?;
-
-    let verdict = if output.exit_status.success() {
-        if stdout.trim() == test.output.trim() {
-            Verdict::Accepted
-        } else {
-            Verdict::WrongAnswer
-        }
-    } else if output.exit_status.code().is_none() {
-        let (memory_usage, memory_limit) =
-            (output.resource_usage.memory_bytes, rlimits.memory_bytes);
-
-        let (time_elapsed, time_limit) = (
-            output.resource_usage.total_time().as_seconds_f64(),
-            rlimits.cpu_seconds as f64,
-        );
-
-        if memory_usage > memory_limit || memory_limit - memory_usage <= MEMORY_USAGE_EPSILON {
-            Verdict::MemoryLimitExceeded
-        } else if time_elapsed > time_limit || time_limit - time_elapsed <= TIME_ELAPSED_EPSILON {
-            Verdict::TimeLimitExceeded
-        } else {
-            panic!("process was killed by signal but it did not exceed the time or memory limit");
+    let mut mismatch = None;
+    let verdict = if let Some(verdict) = exceeded(rlimits, &output) {
+        verdict
+    } else if output.exit_status.success() {
+        let stdout = std::str::from_utf8(&output.stdout)?;
+        match comparison.compare(&test.output, stdout) {
+            Ok(()) => Verdict::Accepted,
+            Err(m) => {
+                tracing::trace!("first mismatch at token {} (line {})", m.token, m.line);
+                mismatch = Some(m);
+                Verdict::WrongAnswer
+            }
         }
     } else {
         Verdict::RuntimeError
@@ -128,5 +151,79 @@ fn test(
     Ok(TestResult {
         verdict,
         resource_usage: Some(output.resource_usage),
+        mismatch,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+
+    use time::Duration;
+
+    use super::*;
+
+    const MB: u64 = 1000 * 1000;
+
+    const RLIMITS: ResourceLimits = ResourceLimits {
+        cpu_seconds: 1,
+        memory_bytes: 256 * MB,
+        output_bytes: MB,
+    };
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn killed(signal: i32) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    fn output(exit_status: ExitStatus, cpu_ms: i64, memory_bytes: u64) -> Output {
+        Output {
+            exit_status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            resource_usage: ResourceUsage {
+                user_time: Duration::milliseconds(cpu_ms),
+                sys_time: Duration::ZERO,
+                memory_bytes,
+            },
+            output_limit_exceeded: false,
+        }
+    }
+
+    #[test]
+    fn exceeded() {
+        let output_exceeded = Output {
+            output_limit_exceeded: true,
+            ..output(killed(libc::SIGKILL), 100, MB)
+        };
+
+        #[rustfmt::skip]
+        let cases = [
+            ("within limits", output(exited(0), 500, 100 * MB), None),
+            ("runtime error", output(exited(1), 500, 100 * MB), None),
+            ("crash", output(killed(libc::SIGSEGV), 500, 100 * MB), None),
+            ("abort", output(killed(libc::SIGABRT), 500, 100 * MB), None),
+            ("broken pipe", output(killed(libc::SIGPIPE), 500, 256 * MB), None),
+            ("killed within limits", output(killed(libc::SIGKILL), 500, 100 * MB), None),
+            ("exited at memory limit", output(exited(1), 500, 256 * MB), None),
+            ("soft CPU limit", output(killed(libc::SIGXCPU), 1000, MB), Some(Verdict::TimeLimitExceeded)),
+            ("hard CPU limit", output(killed(libc::SIGKILL), 2000, MB), Some(Verdict::TimeLimitExceeded)),
+            ("killed near CPU limit", output(killed(libc::SIGKILL), 950, MB), Some(Verdict::TimeLimitExceeded)),
+            ("crash at memory limit", output(killed(libc::SIGSEGV), 500, 256 * MB), Some(Verdict::MemoryLimitExceeded)),
+            ("crash near memory limit", output(killed(libc::SIGSEGV), 500, 256 * MB - 500), Some(Verdict::MemoryLimitExceeded)),
+            ("crash far over memory limit", output(killed(libc::SIGSEGV), 500, 1024 * MB), Some(Verdict::MemoryLimitExceeded)),
+            ("bad_alloc", output(killed(libc::SIGABRT), 500, 256 * MB), Some(Verdict::MemoryLimitExceeded)),
+            ("bus error at memory limit", output(killed(libc::SIGBUS), 500, 256 * MB), Some(Verdict::MemoryLimitExceeded)),
+            ("killed at memory limit", output(killed(libc::SIGKILL), 500, 256 * MB), Some(Verdict::MemoryLimitExceeded)),
+            ("output limit", output_exceeded, Some(Verdict::OutputLimitExceeded)),
+            ("file size limit", output(killed(libc::SIGXFSZ), 100, MB), Some(Verdict::OutputLimitExceeded)),
+        ];
+
+        for (name, output, expected) in cases {
+            assert_eq!(super::exceeded(RLIMITS, &output), expected, "{name}");
+        }
+    }
+}
@@ -4,8 +4,9 @@ use std::{
     io::{self, Read, Write},
     os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::{self, ExitStatus, Stdio},
+    process::{self, ChildStdin, ExitStatus, Stdio},
     str::FromStr,
+    thread,
 };
 
 pub use resource::{ResourceLimits, ResourceUsage};
@@ -66,6 +67,8 @@ pub struct Output {
     pub stdout: Vec<u8>,
     pub stderr: Vec<u8>,
     pub resource_usage: ResourceUsage,
+    /// Whether the process was killed for writing more than the output limit to stdout or stderr
+    pub output_limit_exceeded: bool,
 }
 
 impl Sandbox {
@@ -116,27 +119,24 @@ impl Sandbox {
         }
 
         let mut child = cmd.spawn()?;
-
-        if let Some(stdin) = stdin {
-            if let Err(e) = child.stdin.take().expect("no stdin").write_all(stdin) {
-                if e.kind() != io::ErrorKind::BrokenPipe {
-                    return Err(e);
-                }
-            }
-        }
-
-        let (stdout, stderr) = {
-            let (mut stdout, mut stderr) = (
-                child.stdout.take().expect("no stdout"),
-                child.stderr.take().expect("no stderr"),
-            );
-            let (mut stdout_buf, mut stderr_buf) = (Vec::new(), Vec::new());
-
-            stdout.read_to_end(&mut stdout_buf)?;
-            stderr.read_to_end(&mut stderr_buf)?;
-
-            (stdout_buf, stderr_buf)
-        };
+        let pid = child.id() as i32;
+        let (stdin_pipe, stdout_pipe, stderr_pipe) = (
+            child.stdin.take().expect("no stdin"),
+            child.stdout.take().expect("no stdout"),
+            child.stderr.take().expect("no stderr"),
+        );
+
+        // all three pipes are serviced at once, as the process may block on any one of them
+        // (e.g. echoing its input before reading all of it)
+        let ((stdout, stdout_exceeded), (stderr, stderr_exceeded)) = thread::scope(|scope| {
+            let writer = scope.spawn(move || write_stdin(stdin_pipe, stdin.unwrap_or_default()));
+            let stderr = scope.spawn(move || read_limited(stderr_pipe, rlimits.output_bytes, pid));
+            let stdout = read_limited(stdout_pipe, rlimits.output_bytes, pid);
+
+            writer.join().expect("stdin writer panicked")?;
+            let stderr = stderr.join().expect("stderr reader panicked");
+            Ok::<_, io::Error>((stdout?, stderr?))
+        })?;
 
         let (exit_status, resource_usage) = resource::wait4(child.id() as i32)?;
 
@@ -145,10 +145,36 @@ impl Sandbox {
             stdout,
             stderr,
             resource_usage,
+            output_limit_exceeded: stdout_exceeded || stderr_exceeded,
         })
     }
 }
 
+/// Writes all of `input` to the process's stdin, then closes it
+fn write_stdin(mut stdin: ChildStdin, input: &[u8]) -> io::Result<()> {
+    match stdin.write_all(input) {
+        // the process doesn't have to read all of its input
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Reads `pipe` to the end, unless more than `limit` bytes come through it first, in which case
+/// the process `pid` is killed and the output is cut off at the limit
+fn read_limited(pipe: impl Read, limit: u64, pid: i32) -> io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    pipe.take(limit + 1).read_to_end(&mut buf)?;
+
+    let exceeded = buf.len() as u64 > limit;
+    if exceeded {
+        tracing::warn!("output limit exceeded, killing process");
+        buf.truncate(limit as usize);
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+
+    Ok((buf, exceeded))
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 enum Profile {
     Build,
@@ -156,13 +182,71 @@ enum Profile {
 }
 
 fn sandbox(rlimits: ResourceLimits, profile: Profile) -> io::Result<()> {
-    use io::{Error, ErrorKind};
-
     rlimits.set()?;
 
     if let Profile::Run = profile {
-        seccomp::apply_filters().map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        seccomp::apply_filters().map_err(|e| io::Error::other(e.to_string()))?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Several times the size of a pipe buffer (64 KiB on Linux)
+    const LARGE: usize = 1024 * 1024;
+
+    const RLIMITS: ResourceLimits = ResourceLimits {
+        cpu_seconds: 5,
+        memory_bytes: 512 * 1024 * 1024,
+        output_bytes: 64 * 1024 * 1024,
+    };
+
+    /// Runs a shell script with the build profile, as the run profile's seccomp filter is too strict
+    /// for a shell
+    fn run_sh(script: &str, stdin: &[u8]) -> Output {
+        let sandbox = Sandbox::new().unwrap();
+        let command = Command::new("sh", ["-c", script]);
+        sandbox
+            .exec(&command, Some(stdin), RLIMITS, Profile::Build)
+            .unwrap()
+    }
+
+    #[test]
+    fn echoes_large_input() {
+        let input = b"0123456789abcdef\n".repeat(LARGE / 16);
+        let output = run_sh("cat", &input);
+
+        assert!(output.exit_status.success());
+        assert_eq!(output.stdout, input);
+    }
+
+    #[test]
+    fn large_stderr_before_stdout() {
+        let output = run_sh(&format!("head -c {LARGE} /dev/zero >&2; echo done"), &[]);
+
+        assert!(output.exit_status.success());
+        assert_eq!(output.stderr.len(), LARGE);
+        assert_eq!(output.stdout, b"done\n");
+    }
+
+    #[test]
+    fn large_stderr_before_reading_input() {
+        let input = vec![b'x'; LARGE];
+        let output = run_sh(&format!("head -c {LARGE} /dev/zero >&2; cat"), &input);
+
+        assert!(output.exit_status.success());
+        assert_eq!(output.stderr.len(), LARGE);
+        assert_eq!(output.stdout, input);
+    }
+
+    #[test]
+    fn unread_input() {
+        let output = run_sh("echo done", &vec![b'x'; LARGE]);
+
+        assert!(output.exit_status.success());
+        assert_eq!(output.stdout, b"done\n");
+    }
+}
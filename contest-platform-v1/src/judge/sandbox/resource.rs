@@ -25,6 +25,13 @@ impl ResourceUsage {
 pub struct ResourceLimits {
     pub cpu_seconds: u64,
     pub memory_bytes: u64,
+    /// Limit on each of stdout, stderr and any file written
+    #[serde(default = "default_output_bytes")]
+    pub output_bytes: u64,
+}
+
+fn default_output_bytes() -> u64 {
+    64 * 1024 * 1024
 }
 
 impl ResourceLimits {
@@ -39,6 +46,7 @@ impl ResourceLimits {
             self.memory_bytes,
             self.memory_bytes + RLIMIT_DATA_TOLERANCE,
         )?;
+        setrlimit(Resource::FSIZE, self.output_bytes, self.output_bytes)?;
         Ok(())
     }
 }
@@ -53,14 +61,14 @@ pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
         Err(io::Error::last_os_error())
     } else {
         let rusage = unsafe { rusage.assume_init() };
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
 
         Ok((
             ExitStatus::from_raw(status),
             ResourceUsage {
                 user_time: timeval_to_duration(rusage.ru_utime),
                 sys_time: timeval_to_duration(rusage.ru_stime),
-                memory_bytes: (rusage.ru_maxrss * page_size / 2) as u64, // FIXME
+                // `ru_maxrss` is in KiB
+                memory_bytes: rusage.ru_maxrss as u64 * 1024,
             },
         ))
     }
@@ -80,6 +80,7 @@ pub async fn serve(config: Config) -> error::AppResult<()> {
         contests,
         sessions: Arc::new(RwLock::new(HashMap::new())),
         judge_config,
+        rejudges: Default::default(),
     })
     .merge(auth::router())
     .nest_service("/static", ServeDir::new(config.static_dir))
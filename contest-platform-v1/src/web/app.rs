@@ -34,6 +34,8 @@ pub struct App {
     pub contests: Vec<Arc<Contest>>,
     pub sessions: Arc<RwLock<HashMap<i64, Arc<Session>>>>,
     pub judge_config: Arc<JudgeConfig>,
+    /// Rejudges started from the admin page, by ID
+    pub rejudges: Arc<RwLock<Vec<watch::Receiver<admin::RejudgeProgress>>>>,
 }
 
 pub fn router(app: App) -> Router {
@@ -114,13 +116,7 @@ struct Sessions {
 
 async fn sessions(State(app): State<App>) -> Sessions {
     Sessions {
-        sessions: app
-            .sessions
-            .read()
-            .await
-            .iter()
-            .map(|(_, session)| session.clone())
-            .collect(),
+        sessions: app.sessions.read().await.values().cloned().collect(),
     }
 }
 
@@ -1,19 +1,22 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use askama::Template;
 use axum::{
-    extract::{Extension, Query, State},
+    extract::{Extension, Path, Query, State},
     http::StatusCode,
     response::Response,
-    routing::get,
-    Router,
+    routing::{get, post},
+    Form, Router,
 };
 use axum_login::{permission_required, AuthzBackend};
 use serde::Deserialize;
-use tokio::sync::watch::Sender;
+use serde_with::{serde_as, NoneAsEmptyString};
+use tokio::sync::watch::{self, Sender};
 use tokio_stream::StreamExt;
 
+use super::submit::{judge_submission, record_grade};
 use crate::contest::Contest;
+use crate::judge::{Submission, Verdict};
 use crate::web::{
     app::App,
     auth::{AuthSession, Backend, Permissions, User},
@@ -27,6 +30,8 @@ pub fn router(app: App, tx: Arc<Sender<()>>) -> Router {
         .route("/admin/sessions", get(sessions).post(sessions_action))
         .route("/admin/contests", get(contests).put(create_session))
         .route("/admin/users", get(users).delete(delete_user))
+        .route("/admin/rejudge", post(rejudge))
+        .route("/admin/rejudge/:id", get(rejudge_progress))
         .route_layer(permission_required!(Backend, Permissions::ADMIN))
         .layer(Extension(tx))
         .with_state(app)
@@ -256,3 +261,226 @@ async fn delete_user(
         Ok(StatusCode::OK)
     }
 }
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct RejudgeForm {
+    id: i64,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    task: Option<i64>,
+    #[serde_as(as = "NoneAsEmptyString")]
+    #[serde(default)]
+    submission: Option<i64>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/rejudge_table.html")]
+struct RejudgeTable {
+    id: usize,
+    progress: RejudgeProgress,
+}
+
+/// How far a rejudge started from the admin page has got
+#[derive(Debug, Default, Clone)]
+pub struct RejudgeProgress {
+    diffs: Vec<RejudgeDiff>,
+    /// Submissions left to judge (once they have been looked up)
+    remaining: Option<usize>,
+    finished: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct RejudgeDiff {
+    submission_id: i64,
+    username: String,
+    task_id: i64,
+    old: (Verdict, u32),
+    new: (Verdict, u32),
+    /// Tests whose verdict changed, as (subtask, test, old verdict, new verdict)
+    tests: Vec<(i64, i64, Option<Verdict>, Option<Verdict>)>,
+}
+
+/// Starts judging the submissions of a session again (or only those to one task, or a single one)
+/// against the contest as it is now on disk, updating their results and the leaderboard
+///
+/// This returns straight away, with a table of the results so far that keeps polling
+/// [`rejudge_progress`] until the rejudge is done.
+async fn rejudge(State(app): State<App>, Form(form): Form<RejudgeForm>) -> AppResult<RejudgeTable> {
+    if !app.sessions.read().await.contains_key(&form.id) {
+        return Err(AppError::StatusCode(StatusCode::NOT_FOUND));
+    }
+
+    let (progress, rx) = watch::channel(RejudgeProgress::default());
+    let id = {
+        let mut rejudges = app.rejudges.write().await;
+        rejudges.push(rx);
+        rejudges.len() - 1
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = rejudge_submissions(&app, form, &progress).await {
+            let report = e.into_report();
+            tracing::error!("failed to rejudge submissions: {report:?}");
+            progress.send_modify(|progress| progress.error = Some(report.to_string()));
+        }
+        progress.send_modify(|progress| progress.finished = true);
+    });
+
+    Ok(RejudgeTable {
+        id,
+        progress: RejudgeProgress::default(),
+    })
+}
+
+async fn rejudge_progress(
+    State(app): State<App>,
+    Path(id): Path<usize>,
+) -> AppResult<RejudgeTable> {
+    let progress = app
+        .rejudges
+        .read()
+        .await
+        .get(id)
+        .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?
+        .borrow()
+        .clone();
+
+    Ok(RejudgeTable { id, progress })
+}
+
+async fn rejudge_submissions(
+    app: &App,
+    RejudgeForm {
+        id,
+        task,
+        submission,
+    }: RejudgeForm,
+    progress: &watch::Sender<RejudgeProgress>,
+) -> AppResult<()> {
+    let session = app
+        .sessions
+        .read()
+        .await
+        .get(&id)
+        .cloned()
+        .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
+
+    let path = session.contest.path.clone();
+    let contest = Arc::new(tokio::task::spawn_blocking(move || Contest::load(path)).await??);
+
+    let submissions = sqlx::query!(
+        "SELECT submissions.*, users.username FROM submissions JOIN users ON users.id = submissions.user_id WHERE session_id = ? AND (? IS NULL OR task = ?) AND (? IS NULL OR submissions.id = ?) ORDER BY submissions.id;",
+        id,
+        task,
+        task,
+        submission,
+        submission,
+    )
+    .fetch_all(app.db.pool())
+    .await?;
+
+    progress.send_modify(|progress| progress.remaining = Some(submissions.len()));
+    for submission in submissions {
+        progress.send_modify(|progress| {
+            progress.remaining = progress.remaining.map(|remaining| remaining - 1)
+        });
+
+        if !(1..=contest.tasks.len() as i64).contains(&submission.task) {
+            tracing::warn!(
+                "not rejudging submission {} to task {}, which no longer exists",
+                submission.id,
+                submission.task
+            );
+            continue;
+        }
+
+        let mut tests = BTreeMap::new();
+
+        let mut old_tests = sqlx::query!(
+            "SELECT subtasks.subtask, tests.test, tests.verdict FROM tests JOIN subtasks ON subtasks.id = tests.subtask_id WHERE subtasks.submission_id = ?;",
+            submission.id
+        )
+        .fetch(app.db.pool());
+        while let Some(test) = old_tests.try_next().await? {
+            let verdict = test.verdict.parse()?;
+            tests.insert((test.subtask, test.test), (Some(verdict), None));
+        }
+        drop(old_tests);
+
+        let judged = judge_submission(
+            app.judge_config.clone(),
+            contest.clone(),
+            submission.task as usize - 1,
+            Submission {
+                code: submission.code,
+                language: submission.language,
+            },
+        )
+        .await;
+        let (grade, compile_error) = match judged {
+            Ok(judged) => judged,
+            Err(e) => {
+                tracing::error!(
+                    "failed to rejudge submission {}: {}",
+                    submission.id,
+                    e.into_report()
+                );
+                continue;
+            }
+        };
+
+        for (subtask_idx, subtask) in grade.subtasks.iter().enumerate() {
+            for (test_idx, test) in subtask.tests.iter().enumerate() {
+                let key = (subtask_idx as i64 + 1, test_idx as i64 + 1);
+                tests.entry(key).or_insert((None, None)).1 = Some(test.verdict);
+            }
+        }
+
+        // otherwise a failure part way through would leave the submission without its tests
+        let mut tx = app.db.pool().begin().await?;
+        let verdict = grade.verdict.to_string();
+        sqlx::query!(
+            "UPDATE submissions SET verdict = ?, score = ?, compile_error = ? WHERE id = ?;",
+            verdict,
+            grade.score,
+            compile_error,
+            submission.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        // the old tests are deleted along with their subtasks
+        sqlx::query!(
+            "DELETE FROM subtasks WHERE submission_id = ?;",
+            submission.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        record_grade(&mut tx, submission.id, &grade).await?;
+        tx.commit().await?;
+
+        let diff = RejudgeDiff {
+            submission_id: submission.id,
+            username: submission.username,
+            task_id: submission.task,
+            old: (submission.verdict.parse()?, submission.score as u32),
+            new: (grade.verdict, grade.score),
+            tests: tests
+                .into_iter()
+                .filter(|(_, (old, new))| old != new)
+                .map(|((subtask, test), (old, new))| (subtask, test, old, new))
+                .collect(),
+        };
+        progress.send_modify(|progress| progress.diffs.push(diff));
+    }
+
+    let sessions = &mut app.sessions.write().await;
+    if let Some(session) = sessions.get_mut(&id) {
+        let session = Arc::make_mut(session);
+        session.contest = contest;
+        session.rescore(&app.db).await?;
+    }
+
+    Ok(())
+}
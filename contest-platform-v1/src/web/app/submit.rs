@@ -8,6 +8,7 @@ use axum::{
 };
 
 use axum_typed_multipart::TypedMultipart;
+use sqlx::SqliteConnection;
 
 use time::OffsetDateTime;
 use tokio_stream::StreamExt;
@@ -15,7 +16,8 @@ use tower_cookies::{Cookie, Cookies};
 
 use super::{App, ContestNavigation};
 use crate::{
-    judge::{GradedTask, JudgeError, Language, Submission, Verdict},
+    contest::Contest,
+    judge::{Config as JudgeConfig, GradedTask, JudgeError, Language, Submission, Verdict},
     web::{auth::AuthSession, error::*, session::UserTask},
 };
 
@@ -199,7 +201,7 @@ pub async fn submit(
 
     let now = OffsetDateTime::now_utc();
 
-    let judge_result = {
+    let (grade, compile_error) = {
         let session = app
             .sessions
             .read()
@@ -221,42 +223,24 @@ pub async fn submit(
 
         tracing::trace!("received submission from user (ID: {user_id}) for task {task_id} of contest session {session_id}");
 
-        let config = app.judge_config.clone();
-        let submission = submission.clone();
-        let task = session
-            .contest
-            .tasks
-            .get(task_id as usize - 1)
-            .cloned()
-            .ok_or(AppError::StatusCode(StatusCode::NOT_FOUND))?;
-
-        tokio::task::spawn_blocking(move || {
-            use crate::judge;
-
-            let verdicts = judge::run(&config, submission, &task, session.contest.rlimits)?;
-            let grade = judge::grade(&task, &verdicts);
+        if !(1..=session.contest.tasks.len() as i64).contains(&task_id) {
+            return Err(AppError::StatusCode(StatusCode::NOT_FOUND));
+        }
 
-            Ok::<_, JudgeError>(grade)
-        })
+        judge_submission(
+            app.judge_config.clone(),
+            session.contest.clone(),
+            task_id as usize - 1,
+            submission.clone(),
+        )
         .await?
     };
 
-    let (grade, compile_error) = match judge_result {
-        Ok(grade) => (grade, None),
-        Err(JudgeError::CompileError(stderr)) => (
-            GradedTask {
-                verdict: Verdict::CompileError,
-                score: 0,
-                subtasks: Vec::new(),
-            },
-            Some(stderr),
-        ),
-        Err(e) => return Err(e.into()),
-    };
-
     let verdict = grade.verdict.to_string();
     let score = grade.score;
 
+    // a submission is only stored along with its tests
+    let mut tx = app.db.pool().begin().await?;
     let submission_id = sqlx::query!(
         "INSERT INTO submissions (user_id, session_id, task, datetime, code, language, verdict, score, compile_error) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
         user_id,
@@ -269,8 +253,72 @@ pub async fn submit(
         score,
         compile_error,
     )
-    .execute(app.db.pool()).await?.last_insert_rowid();
+    .execute(&mut *tx).await?.last_insert_rowid();
+
+    record_grade(&mut tx, submission_id, &grade).await?;
+    tx.commit().await?;
+
+    cookies.add(Cookie::new(LANGUAGE_COOKIE, submission.language));
 
+    let sessions = &mut app.sessions.write().await;
+    let session = Arc::make_mut(sessions.get_mut(&session_id).unwrap());
+
+    session
+        .users
+        .entry((user_id, task_id))
+        .and_modify(|user_task| user_task.score = user_task.score.max(score))
+        .or_insert_with(|| UserTask {
+            score,
+            cooldown: OffsetDateTime::now_utc(),
+        });
+
+    session.update_leaderboard(user.username(), user_id)?;
+
+    tracing::trace!("submission successfully judged and recorded");
+
+    Ok(Redirect::to(&redirect_url))
+}
+
+/// Judges a submission to a task (by index) of a contest, returning the compiler's stderr as well
+/// if it failed to compile
+pub(super) async fn judge_submission(
+    config: Arc<JudgeConfig>,
+    contest: Arc<Contest>,
+    task_idx: usize,
+    submission: Submission,
+) -> AppResult<(GradedTask, Option<String>)> {
+    let judge_result = tokio::task::spawn_blocking(move || {
+        use crate::judge;
+
+        let task = &contest.tasks[task_idx];
+        let comparison = task.comparison.unwrap_or(contest.comparison);
+        let verdicts = judge::run(&config, submission, task, contest.rlimits, comparison)?;
+        let grade = judge::grade(task, &verdicts);
+
+        Ok::<_, JudgeError>(grade)
+    })
+    .await?;
+
+    match judge_result {
+        Ok(grade) => Ok((grade, None)),
+        Err(JudgeError::CompileError(stderr)) => Ok((
+            GradedTask {
+                verdict: Verdict::CompileError,
+                score: 0,
+                subtasks: Vec::new(),
+            },
+            Some(stderr),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Stores the subtask and test results of a graded submission
+pub(super) async fn record_grade(
+    conn: &mut SqliteConnection,
+    submission_id: i64,
+    grade: &GradedTask,
+) -> sqlx::Result<()> {
     for (idx, subtask) in grade.subtasks.iter().enumerate() {
         let subtask_idx = idx as i64 + 1;
         let subtask_verdict = subtask.verdict.to_string();
@@ -283,7 +331,7 @@ pub async fn submit(
             subtask_verdict,
             subtask_score
         )
-        .execute(app.db.pool())
+        .execute(&mut *conn)
         .await?
         .last_insert_rowid();
 
@@ -308,27 +356,9 @@ pub async fn submit(
                 test_verdict,
                 test_score
             )
-            .execute(app.db.pool()).await?;
+            .execute(&mut *conn).await?;
         }
     }
 
-    cookies.add(Cookie::new(LANGUAGE_COOKIE, submission.language));
-
-    let sessions = &mut app.sessions.write().await;
-    let session = Arc::make_mut(sessions.get_mut(&session_id).unwrap());
-
-    session
-        .users
-        .entry((user_id, task_id))
-        .and_modify(|user_task| user_task.score = user_task.score.max(score))
-        .or_insert_with(|| UserTask {
-            score,
-            cooldown: OffsetDateTime::now_utc(),
-        });
-
-    session.update_leaderboard(user.username(), user_id)?;
-
-    tracing::trace!("submission successfully judged and recorded");
-
-    Ok(Redirect::to(&redirect_url))
+    Ok(())
 }
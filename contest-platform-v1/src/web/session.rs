@@ -108,6 +108,47 @@ impl Session {
         }
     }
 
+    /// Recomputes every user's best score for each task from the database and rebuilds the
+    /// leaderboard, since rejudging can lower scores as well as raise them
+    pub async fn rescore(&mut self, db: &Database) -> SessionResult<()> {
+        let best = sqlx::query!(
+            r#"SELECT submissions.user_id AS "user_id!", submissions.task AS "task!", users.username AS "username!", MAX(submissions.score) AS "score!: i64"
+            FROM submissions JOIN users ON users.id = submissions.user_id
+            WHERE submissions.session_id = ?
+            GROUP BY submissions.user_id, submissions.task;"#,
+            self.id
+        )
+        .fetch_all(db.pool())
+        .await?;
+
+        let mut totals: HashMap<i64, (String, u32)> = HashMap::new();
+        for row in best {
+            let score = row.score as u32;
+            self.users
+                .entry((row.user_id, row.task))
+                .and_modify(|user_task| user_task.score = score)
+                .or_insert(UserTask {
+                    score,
+                    cooldown: OffsetDateTime::UNIX_EPOCH,
+                });
+            totals.entry(row.user_id).or_insert((row.username, 0)).1 += score;
+        }
+
+        self.leaderboard = Leaderboard::new();
+        for (user_id, (username, score)) in totals {
+            self.leaderboard.update(LeaderboardEntry {
+                score,
+                username,
+                user_id,
+            });
+        }
+
+        // the session holds a receiver itself, so sending can't fail
+        self.tx.send(()).ok();
+
+        Ok(())
+    }
+
     pub fn update_leaderboard(
         &mut self,
         username: &str,
@@ -0,0 +1,395 @@
+//! Recommends resource limits for a contest by timing solutions to its tasks.
+//!
+//! Usage: `calibrate [--runs <n>] [--max-cpu <seconds>] <contest> <solutions>`
+//!
+//! Solutions are named after the task number, with the extension of the language they are written
+//! in, optionally followed by a label (e.g. `1.cpp` or `1-greedy.cpp`). Solutions with a label
+//! starting with `slow` (e.g. `1-slow-brute-force.py`) are known to be too slow, and the
+//! recommended CPU time limit is chosen to fail them where possible. Each solution is run several
+//! times on every test, without checking its output (use `validate` for that).
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use judge::{
+    contest::{Config, Contest, Kind, LimitOverrides, Task},
+    interactor,
+    registry::{self, Loaded},
+    sandbox::{self, Profile, ResourceLimits},
+};
+use serde::Serialize;
+use tokio::fs;
+use uuid::Uuid;
+use yansi::Paint;
+
+const USAGE: &str = "usage: calibrate [--runs <n>] [--max-cpu <seconds>] <contest> <solutions>";
+
+/// Ratio between the CPU time limit and the time taken by the slowest model solution
+const SAFETY_FACTOR: f64 = 2.0;
+/// Smallest recommended CPU time tolerance (seconds)
+const MIN_TOLERANCE: f64 = 0.05;
+/// Recommended CPU time limits are whole numbers of these per second (i.e. tenths of a second),
+/// counted as integers so that they print without float error
+const STEPS: f64 = 10.0;
+
+struct Solution {
+    path: PathBuf,
+    language: usize,
+    slow: bool,
+}
+
+/// CPU time (user and system) taken by a solution on its slowest test
+#[derive(Default)]
+struct Timing {
+    /// Slowest test (numbered from 1, as subtask-test)
+    test: (usize, usize),
+    /// Fastest run on the slowest test
+    min: Duration,
+    /// Slowest run on the slowest test
+    max: Duration,
+    /// Largest difference between runs on any test
+    spread: Duration,
+    /// Largest memory usage on any test (bytes)
+    memory: u64,
+    /// Whether any run reached the calibration CPU time limit
+    capped: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Suggestion {
+    resource_limits: LimitOverrides,
+}
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<ExitCode> {
+    dotenvy::dotenv().ok();
+    color_eyre::install()?;
+
+    let mut args = env::args().skip(1);
+    let (mut runs, mut max_cpu, mut paths) = (5, 10.0, Vec::new());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => {
+                runs = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or(eyre!(USAGE))?
+            }
+            "--max-cpu" => {
+                max_cpu = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or(eyre!(USAGE))?
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let [contest_path, solutions] = &paths[..] else {
+        bail!(USAGE);
+    };
+    if runs == 0 {
+        bail!("at least one run is required");
+    }
+
+    let contest_path = Path::new(contest_path).canonicalize()?;
+    let solutions = Path::new(solutions).canonicalize()?;
+
+    let contest = registry::read(&contest_path)
+        .await?
+        .ok_or_else(|| eyre!("{} is not a contest", contest_path.display()))?;
+    let name = contest_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("contest")
+        .to_owned();
+
+    sandbox::probe().await;
+
+    let mut files = Vec::new();
+    let mut read_dir = fs::read_dir(&solutions)
+        .await
+        .wrap_err("failed to scan solutions directory")?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        files.push(entry.path());
+    }
+    files.sort_unstable();
+
+    // solutions are compiled and run in a scratch directory
+    let cwd = env::current_dir()?;
+    let scratch = env::temp_dir().join(format!("judge-calibrate-{}", Uuid::new_v4()));
+    fs::create_dir(&scratch).await?;
+    env::set_current_dir(&scratch)?;
+
+    let result = calibrate(&name, contest, &files, runs, max_cpu).await;
+
+    env::set_current_dir(cwd)?;
+    fs::remove_dir_all(&scratch).await?;
+
+    let recommended = result?;
+    if recommended.is_empty() {
+        println!("no model solutions found, nothing to recommend");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    for (task, resource_limits) in recommended {
+        let suggestion = Suggestion { resource_limits };
+        println!("\nsuggested resource limits for task {task}:\n");
+        if contest_path.is_dir() {
+            print!("{}", toml::to_string(&suggestion)?);
+        } else {
+            println!("{}", serde_json::to_string_pretty(&suggestion)?);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Times every solution, returning the recommended limits for each task (numbered from 1)
+async fn calibrate(
+    name: &str,
+    contest: Contest,
+    files: &[PathBuf],
+    runs: usize,
+    max_cpu: f64,
+) -> color_eyre::Result<Vec<(usize, LimitOverrides)>> {
+    let contest = Loaded::new(name, contest).await?;
+    let mut recommended = Vec::new();
+
+    for (idx, task) in contest.tasks.iter().enumerate() {
+        let number = idx + 1;
+        println!("{}", format!("task {number} ({})", task.name).bold());
+
+        if task.answer.is_some() {
+            println!("  answer task, skipping");
+            continue;
+        }
+
+        // slowest model solution, and fastest known-slow solution (without language multipliers)
+        let (mut fast, mut spread) = (None, Duration::ZERO);
+        let mut slow: Option<(Duration, PathBuf)> = None;
+
+        for solution in solutions(&contest.config, number, files) {
+            let timing = time(&contest, number, task, &solution, max_cpu, runs)
+                .await
+                .wrap_err_with(|| format!("failed to time {}", solution.path.display()))?;
+
+            let capped = if timing.capped { "+" } else { "" };
+            println!(
+                "  {:<24} {:>8.3}s{capped} (test {}-{}, spread {:.3}s, memory {} bytes){}",
+                solution.path.file_name().unwrap().to_string_lossy(),
+                timing.max.as_secs_f64(),
+                timing.test.0,
+                timing.test.1,
+                timing.spread.as_secs_f64(),
+                timing.memory,
+                if solution.slow { " [slow]" } else { "" },
+            );
+
+            let multiplier = contest.config.languages[solution.language]
+                .time_multiplier
+                .unwrap_or(1.0);
+            let (min, max) = (
+                timing.min.div_f64(multiplier),
+                timing.max.div_f64(multiplier),
+            );
+
+            if !solution.slow {
+                fast = fast.max(Some(max));
+                spread = spread.max(timing.spread.div_f64(multiplier));
+            } else if slow.as_ref().is_none_or(|(slow, _)| min < *slow) {
+                slow = Some((min, solution.path));
+            }
+        }
+
+        let Some(fast) = fast else {
+            println!("  {}: no model solution", "warning".yellow().bold());
+            continue;
+        };
+
+        let tolerance = (spread.as_secs_f64().max(MIN_TOLERANCE) * 100.0).ceil() / 100.0;
+        let fast = fast.as_secs_f64();
+        let mut cpu = steps_up(fast * SAFETY_FACTOR + tolerance).max(1);
+
+        if let Some((slow, path)) = slow {
+            let slow = slow.as_secs_f64();
+            if slow <= seconds(cpu) + tolerance {
+                // the largest limit that still fails the slow solution (no limit at all if the
+                // slow solution is within the tolerance)
+                let tight = steps_up(slow - tolerance).saturating_sub(1);
+                if tight > 0 && seconds(tight) > fast + tolerance {
+                    cpu = tight;
+                } else {
+                    println!(
+                        "  {}: {} is too close to the model solutions to fail it",
+                        "warning".yellow().bold(),
+                        path.display()
+                    );
+                }
+            }
+        }
+
+        let cpu = seconds(cpu);
+        println!("  recommended: cpu = {cpu}, cpu-tolerance = {tolerance}");
+        recommended.push((
+            number,
+            LimitOverrides {
+                cpu: Some(cpu),
+                cpu_tolerance: Some(tolerance),
+                ..Default::default()
+            },
+        ));
+    }
+
+    Ok(recommended)
+}
+
+/// Rounds a CPU time limit up to a whole number of [`STEPS`]
+fn steps_up(seconds: f64) -> u32 {
+    // otherwise float error (e.g. in 0.1 + 0.2) would round up by a whole step
+    (seconds * STEPS - 1e-9).ceil().max(0.0) as u32
+}
+
+fn seconds(steps: u32) -> f64 {
+    f64::from(steps) / STEPS
+}
+
+/// Solutions to a task (numbered from 1) in one of the contest's languages
+fn solutions(config: &Config, task: usize, files: &[PathBuf]) -> Vec<Solution> {
+    let task = task.to_string();
+
+    files
+        .iter()
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?;
+            let label = match stem.split_once('-') {
+                Some((number, label)) if number == task => label,
+                None if stem == task => "",
+                _ => return None,
+            };
+
+            let Some(language) = config.language_for(path) else {
+                println!(
+                    "  {}: no language for {}",
+                    "warning".yellow().bold(),
+                    path.display()
+                );
+                return None;
+            };
+
+            Some(Solution {
+                path: path.clone(),
+                language,
+                slow: label.starts_with("slow"),
+            })
+        })
+        .collect()
+}
+
+async fn time(
+    contest: &Arc<Loaded>,
+    number: usize,
+    task: &Task,
+    solution: &Solution,
+    max_cpu: f64,
+    runs: usize,
+) -> color_eyre::Result<Timing> {
+    let language = &contest.config.languages[solution.language];
+
+    let dir = Path::new("solutions").join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&dir).await?;
+    fs::copy(&solution.path, dir.join(&language.filename)).await?;
+
+    if let Some(command) = &language.compile {
+        let profile = Profile::Compile(language.compile_limits(&contest.config));
+        let output = sandbox::run(&dir, command, &[], profile).await?;
+        if !output.exit_status().success() {
+            bail!(
+                "failed to compile: {}",
+                output.stderr_utf8().unwrap_or_default()
+            );
+        }
+    }
+
+    let filter = language.syscall_filter(&contest.config)?;
+    let mut timing = Timing::default();
+    let mut tests = 0;
+
+    // known-slow solutions are only run once, until they reach the calibration limit
+    let runs = if solution.slow { 1 } else { runs };
+
+    'tests: for (subtask_idx, subtask) in task.subtasks.iter().enumerate() {
+        let limits = ResourceLimits {
+            cpu: max_cpu,
+            wall_time: None,
+            ..task.resource_limits(&contest.config, subtask_idx)
+        };
+
+        for (test_idx, test) in subtask.tests.iter().enumerate() {
+            let mut times = Vec::with_capacity(runs);
+
+            for _ in 0..runs {
+                let usage = match &task.kind {
+                    Kind::Batch => sandbox::run(
+                        &dir,
+                        &language.run,
+                        test.input.as_bytes(),
+                        Profile::Run(limits, filter.clone()),
+                    )
+                    .await?
+                    .resource_usage(),
+                    Kind::Interactive { interactor } => interactor::interact(
+                        &dir,
+                        language,
+                        &contest.programs(number).join("interactor"),
+                        &contest.config,
+                        interactor,
+                        limits,
+                        task.resource_limits(&contest.config, subtask_idx),
+                        test,
+                    )
+                    .await?
+                    .contestant
+                    .resource_usage(),
+                };
+
+                times.push(usage.total_time());
+                timing.memory = timing.memory.max(usage.memory);
+                timing.capped |= usage.total_time().as_secs_f64() >= limits.cpu;
+            }
+
+            tests += 1;
+            let min = times.iter().min().copied().unwrap_or_default();
+            let max = times.iter().max().copied().unwrap_or_default();
+            timing.spread = timing.spread.max(max - min);
+
+            if max >= timing.max {
+                timing.test = (subtask_idx + 1, test_idx + 1);
+                (timing.min, timing.max) = (min, max);
+            }
+
+            if solution.slow && timing.capped {
+                break 'tests;
+            }
+        }
+    }
+
+    fs::remove_dir_all(&dir).await?;
+
+    if tests == 0 {
+        bail!("task has no tests");
+    }
+
+    Ok(timing)
+}
@@ -1,7 +1,7 @@
 use std::fs;
 
 use color_eyre::eyre::WrapErr;
-use judge::{contest::Contest, submit::Message};
+use judge::{contest::Contest, rejudge::Diff, store::Record, submit::Message};
 use schemars::schema_for;
 
 fn main() -> color_eyre::Result<()> {
@@ -18,5 +18,15 @@ fn main() -> color_eyre::Result<()> {
     fs::write("schema/message.json", message).wrap_err("failed to write schema/message.json")?;
     println!("generated schema/message.json");
 
+    let record = serde_json::to_string_pretty(&schema_for!(Record))
+        .wrap_err("failed to serialize JSON schema for Record")?;
+    fs::write("schema/record.json", record).wrap_err("failed to write schema/record.json")?;
+    println!("generated schema/record.json");
+
+    let diff = serde_json::to_string_pretty(&schema_for!(Diff))
+        .wrap_err("failed to serialize JSON schema for Diff")?;
+    fs::write("schema/diff.json", diff).wrap_err("failed to write schema/diff.json")?;
+    println!("generated schema/diff.json");
+
     Ok(())
 }
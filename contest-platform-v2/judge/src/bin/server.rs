@@ -1,18 +1,28 @@
-use std::{ffi::OsStr, net::SocketAddr, path::Path};
+use std::{convert::Infallible, net::SocketAddr, path::Path, sync::Arc};
 
-use ahash::AHashMap;
 use axum::{
-    http::{Method, StatusCode},
+    extract,
+    http::{header, HeaderMap, HeaderName, Method, StatusCode},
     response::{sse::Event, IntoResponse, Response, Sse},
-    routing::post,
-    Router,
+    routing::{get, post},
+    Json, Router,
 };
-use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use axum_typed_multipart::{TryFromField, TryFromMultipart, TypedMultipart};
 use color_eyre::eyre::WrapErr;
-use judge::{contest::Contest, submit::submit, CONTESTS};
+use judge::{
+    cache, constant_time_eq,
+    queue::{self, Priority, Queued},
+    registry::{self, Loaded},
+    rejudge::{self, RejudgeError, Selection},
+    sandbox,
+    store::{self, Entry, Events, Record},
+    submit::{submit_answer, Job, ANSWER_FILENAME},
+    worker, CONTESTS,
+};
+use once_cell::sync::Lazy;
 use thiserror::Error;
-use tokio::{fs, net::TcpListener, sync::mpsc};
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::{fs, net::TcpListener};
+use tokio_stream::{adapters::Map, wrappers::ReceiverStream, StreamExt};
 use tower::ServiceBuilder;
 use tower_http::{
     cors::{Any, CorsLayer},
@@ -23,15 +33,76 @@ use tracing_subscriber::{prelude::*, EnvFilter};
 use tracing_tree::HierarchicalLayer;
 use uuid::Uuid;
 
+const CONTESTS_DIR: &str = "contests";
+
+/// Token required to use the admin endpoints, which are disabled if it isn't set
+static ADMIN_TOKEN: Lazy<Option<String>> = Lazy::new(|| {
+    std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+});
+
+/// Token required to connect as a remote worker, which aren't accepted if it isn't set
+static WORKER_TOKEN: Lazy<Option<String>> = Lazy::new(|| {
+    std::env::var("WORKER_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+});
+
 #[derive(TryFromMultipart)]
 struct SubmitRequest {
     contest: String,
     task: usize,
     language: String,
     code: String,
+    /// User making the submission, whose submissions are queued fairly with other users'
+    user: Option<String>,
+    /// Defaults to `contest`
+    priority: Option<SubmitPriority>,
+}
+
+/// Priorities that submissions can be queued with (rejudges are queued by the judge itself)
+#[derive(Debug, TryFromField)]
+#[try_from_field(rename_all = "kebab-case")]
+enum SubmitPriority {
+    Contest,
+    Practice,
+}
+
+#[derive(TryFromMultipart)]
+struct AnswerRequest {
+    contest: String,
+    task: usize,
+    answer: String,
+    user: Option<String>,
+}
+
+type Stream = Sse<Map<Events, fn((Option<usize>, String)) -> Result<Event, Infallible>>>;
+
+fn stream(events: Events) -> Stream {
+    Sse::new(events.map(|(id, json)| {
+        let event = Event::default().data(json);
+        // updates that aren't stored can't be resumed from
+        Ok(match id {
+            Some(id) => event.id(id.to_string()),
+            None => event,
+        })
+    }))
 }
 
-type Stream = Sse<ReceiverStream<Result<Event, std::convert::Infallible>>>;
+/// Header carrying the ID of a new submission (which is also in its first message)
+const SUBMISSION_ID: HeaderName = HeaderName::from_static("x-submission-id");
+
+/// Stream of a new submission's messages, with headers pointing to where it can be looked up
+type Submitted = ([(HeaderName, String); 2], Stream);
+
+fn submitted(id: Uuid, events: Events) -> Submitted {
+    let headers = [
+        (header::LOCATION, format!("/submissions/{id}")),
+        (SUBMISSION_ID, id.to_string()),
+    ];
+    (headers, stream(events))
+}
 
 #[derive(Debug, Error)]
 enum SubmitError {
@@ -41,6 +112,10 @@ enum SubmitError {
     TaskNotFound(String, usize),
     #[error("unsupported language: {0}")]
     UnsupportedLanguage(String),
+    #[error("task #{1} for contest {0} takes an answer, not code")]
+    AnswerExpected(String, usize),
+    #[error("task #{1} for contest {0} takes code, not an answer")]
+    CodeExpected(String, usize),
     #[error("IO error: {0}")]
     Io(#[from] tokio::io::Error),
 }
@@ -51,7 +126,9 @@ impl IntoResponse for SubmitError {
             SubmitError::ContestNotFound(_) | SubmitError::TaskNotFound(_, _) => {
                 StatusCode::NOT_FOUND
             }
-            SubmitError::UnsupportedLanguage(_) => StatusCode::BAD_REQUEST,
+            SubmitError::UnsupportedLanguage(_)
+            | SubmitError::AnswerExpected(_, _)
+            | SubmitError::CodeExpected(_, _) => StatusCode::BAD_REQUEST,
             SubmitError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -59,6 +136,23 @@ impl IntoResponse for SubmitError {
     }
 }
 
+/// Looks up a contest and checks that it has a task with the given number (starting from 1)
+fn find_task(contest_name: &str, task_index: usize) -> Result<Arc<Loaded>, SubmitError> {
+    let contest = registry::contests()
+        .get(contest_name)
+        .cloned()
+        .ok_or_else(|| SubmitError::ContestNotFound(contest_name.to_owned()))?;
+
+    if !(1..=contest.tasks.len()).contains(&task_index) {
+        return Err(SubmitError::TaskNotFound(
+            contest_name.to_owned(),
+            task_index,
+        ));
+    }
+
+    Ok(contest)
+}
+
 #[tracing::instrument(skip(language_name, code), err)]
 async fn handler(
     TypedMultipart(SubmitRequest {
@@ -66,35 +160,212 @@ async fn handler(
         task: task_index,
         language: language_name,
         code,
+        user,
+        priority,
     }): TypedMultipart<SubmitRequest>,
-) -> Result<Stream, SubmitError> {
-    let contests = CONTESTS.get().unwrap();
-
-    let contest = contests
-        .get(&contest_name)
-        .ok_or_else(|| SubmitError::ContestNotFound(contest_name.clone()))?;
-
-    let task = task_index
-        .checked_sub(1)
-        .and_then(|idx| contest.tasks.get(idx))
-        .ok_or_else(|| SubmitError::TaskNotFound(contest_name, task_index))?;
+) -> Result<Submitted, SubmitError> {
+    let contest = find_task(&contest_name, task_index)?;
+    if contest.tasks[task_index - 1].answer.is_some() {
+        return Err(SubmitError::AnswerExpected(contest_name, task_index));
+    }
 
     let language = contest
         .config
         .languages
         .iter()
         .find(|lang| lang.name == language_name)
-        .ok_or_else(|| SubmitError::UnsupportedLanguage(language_name))?;
+        .ok_or_else(|| SubmitError::UnsupportedLanguage(language_name.clone()))?;
 
     let uuid = Uuid::new_v4();
-    let dir = Path::new("submissions").join(uuid.to_string());
+    let dir = store::dir(uuid);
     fs::create_dir(&dir).await?;
-    fs::write(dir.join(&language.filename), code).await?;
+    fs::write(dir.join(&language.filename), &code).await?;
+
+    let record = Record::new(
+        uuid,
+        contest_name.clone(),
+        task_index,
+        user.clone(),
+        Some(language_name.clone()),
+    );
+    let entry = Entry::create(record).await?;
+    let events = entry.subscribe(0);
+
+    let job = Job {
+        id: uuid,
+        contest: contest_name,
+        task: task_index,
+        language: language_name,
+        code,
+        version: contest.version().to_owned(),
+    };
+    let priority = match priority {
+        None | Some(SubmitPriority::Contest) => Priority::Contest,
+        Some(SubmitPriority::Practice) => Priority::Practice,
+    };
+    queue::enqueue(Queued {
+        job,
+        entry,
+        contest,
+        priority,
+        user,
+    });
+
+    Ok(submitted(uuid, events))
+}
+
+/// Judges an answer to a task that has one, streaming the same messages as a code submission
+#[tracing::instrument(skip(answer), err)]
+async fn answer_handler(
+    TypedMultipart(AnswerRequest {
+        contest: contest_name,
+        task: task_index,
+        answer,
+        user,
+    }): TypedMultipart<AnswerRequest>,
+) -> Result<Submitted, SubmitError> {
+    let contest = find_task(&contest_name, task_index)?;
+    if contest.tasks[task_index - 1].answer.is_none() {
+        return Err(SubmitError::CodeExpected(contest_name, task_index));
+    }
+
+    let uuid = Uuid::new_v4();
+    let dir = store::dir(uuid);
+    fs::create_dir(&dir).await?;
+    fs::write(dir.join(ANSWER_FILENAME), &answer).await?;
+
+    let record = Record::new(uuid, contest_name, task_index, user, None);
+    let entry = Entry::create(record).await?;
+    let events = entry.subscribe(0);
+
+    tokio::spawn(submit_answer(entry, contest, task_index, answer));
+
+    Ok(submitted(uuid, events))
+}
+
+#[derive(Debug, Error)]
+enum SubmissionError {
+    #[error("submission {0} not found")]
+    NotFound(Uuid),
+    #[error("IO error: {0}")]
+    Io(#[from] tokio::io::Error),
+}
 
-    let (tx, rx) = mpsc::channel(64);
-    tokio::spawn(submit(tx, dir, &contest.config, task, language));
+impl IntoResponse for SubmissionError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            SubmissionError::NotFound(_) => StatusCode::NOT_FOUND,
+            SubmissionError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[tracing::instrument(err)]
+async fn submission(
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Record>, SubmissionError> {
+    let record = store::record(id)
+        .await?
+        .ok_or(SubmissionError::NotFound(id))?;
+
+    Ok(Json(record))
+}
+
+/// Replays the messages of a submission, resuming after the `Last-Event-ID` header if present
+#[tracing::instrument(skip(headers), err)]
+async fn submission_events(
+    extract::Path(id): extract::Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Stream, SubmissionError> {
+    let from = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .map_or(0, |id| id + 1);
+
+    let events = store::events(id, from)
+        .await?
+        .ok_or(SubmissionError::NotFound(id))?;
+
+    Ok(stream(events))
+}
 
-    Ok(Sse::new(ReceiverStream::new(rx)))
+#[derive(Debug, Error)]
+enum AdminError {
+    #[error("admin endpoints are disabled (ADMIN_TOKEN is not set)")]
+    Disabled,
+    #[error("invalid admin token")]
+    Unauthorized,
+    #[error("failed to reload contests: {0:#}")]
+    Load(color_eyre::Report),
+    #[error(transparent)]
+    Rejudge(#[from] RejudgeError),
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AdminError::Disabled => StatusCode::FORBIDDEN,
+            AdminError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AdminError::Load(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AdminError::Rejudge(
+                RejudgeError::ContestNotFound(_)
+                | RejudgeError::TaskNotFound(_, _)
+                | RejudgeError::SubmissionNotFound(_),
+            ) => StatusCode::NOT_FOUND,
+            AdminError::Rejudge(RejudgeError::Io(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Checks that a request to an admin endpoint carries the admin token
+fn authorize(headers: &HeaderMap) -> Result<(), AdminError> {
+    let token = ADMIN_TOKEN.as_deref().ok_or(AdminError::Disabled)?;
+
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err(AdminError::Unauthorized);
+    }
+
+    Ok(())
+}
+
+/// Reloads the contest definitions, returning the names of the contests now being served
+///
+/// Submissions that are being judged keep using the contest definitions they started with.
+#[tracing::instrument(skip_all, err)]
+async fn reload(headers: HeaderMap) -> Result<Json<Vec<String>>, AdminError> {
+    authorize(&headers)?;
+
+    let names = registry::reload(Path::new(CONTESTS_DIR))
+        .await
+        .map_err(AdminError::Load)?;
+
+    Ok(Json(names))
+}
+
+/// Judges submissions again against the current contest definitions, streaming the diff of each
+/// one as it finishes
+#[tracing::instrument(skip(headers), err)]
+async fn rejudge_handler(
+    headers: HeaderMap,
+    Json(selection): Json<Selection>,
+) -> Result<Sse<impl tokio_stream::Stream<Item = Result<Event, axum::Error>>>, AdminError> {
+    authorize(&headers)?;
+
+    let diffs = rejudge::rejudge(selection).await?;
+
+    Ok(Sse::new(
+        ReceiverStream::new(diffs).map(|diff| Event::default().json_data(diff)),
+    ))
 }
 
 #[tokio::main]
@@ -108,48 +379,65 @@ async fn main() -> color_eyre::Result<()> {
         .try_init()
         .wrap_err("failed to initialize tracing")?;
 
-    let contests = {
-        let mut contests = AHashMap::new();
+    sandbox::probe().await;
 
-        let mut read_dir = fs::read_dir("contests")
-            .await
-            .wrap_err("failed to scan contests directory")?;
-        while let Some(entry) = read_dir.next_entry().await? {
-            let path = entry.path();
-            if path.extension().and_then(OsStr::to_str) == Some("json") {
-                let input = fs::read_to_string(&path).await?;
-                let contest = Contest::load(&input)?;
-                tracing::info!("loaded contest {} ({})", contest.name, path.display());
-                contests.insert(
-                    path.file_stem()
-                        .unwrap()
-                        .to_str()
-                        .expect("non UTF-8 filename")
-                        .to_owned(),
-                    contest,
-                );
-            }
-        }
-
-        contests
-    };
+    // helper programs from previous runs
+    if Path::new("programs").is_dir() {
+        fs::remove_dir_all("programs").await?;
+    }
 
-    CONTESTS.set(contests).unwrap();
+    let contests = registry::load(Path::new(CONTESTS_DIR)).await?;
+    CONTESTS.store(Arc::new(contests));
 
     if !Path::new("submissions").is_dir() {
         tracing::warn!("submissions directory not found, creating it");
         fs::create_dir("submissions").await?;
     }
 
+    let cache_size = match std::env::var("COMPILE_CACHE_SIZE") {
+        Ok(size) => size
+            .parse()
+            .wrap_err("COMPILE_CACHE_SIZE must be a number")?,
+        Err(_) => cache::DEFAULT_SIZE,
+    };
+    cache::init(cache_size)
+        .await
+        .wrap_err("failed to set up compile cache")?;
+
+    let local_workers = match std::env::var("LOCAL_WORKERS") {
+        Ok(count) => count.parse().wrap_err("LOCAL_WORKERS must be a number")?,
+        Err(_) => queue::default_local_workers(),
+    };
+    queue::spawn_local_workers(local_workers);
+
+    if let Some(token) = WORKER_TOKEN.clone() {
+        let addr = SocketAddr::from(([0; 4], worker::PORT));
+        let listener = TcpListener::bind(addr)
+            .await
+            .wrap_err("failed to bind to worker port")?;
+        tracing::info!("accepting workers on {addr}");
+        tokio::spawn(worker::serve(listener, token));
+    } else if local_workers == 0 {
+        tracing::warn!("no local workers and WORKER_TOKEN is not set, so nothing will be judged");
+    }
+
     let services = ServiceBuilder::new()
         .layer(TraceLayer::new_for_http())
         .layer(
             CorsLayer::new()
-                .allow_methods([Method::POST])
-                .allow_origin(Any),
+                .allow_methods([Method::GET, Method::POST])
+                .allow_origin(Any)
+                .expose_headers([header::LOCATION, SUBMISSION_ID]),
         );
 
-    let app = Router::new().route("/", post(handler)).layer(services);
+    let app = Router::new()
+        .route("/", post(handler))
+        .route("/answer", post(answer_handler))
+        .route("/submissions/:id", get(submission))
+        .route("/submissions/:id/events", get(submission_events))
+        .route("/admin/reload", post(reload))
+        .route("/admin/rejudge", post(rejudge_handler))
+        .layer(services);
 
     let addr = SocketAddr::from(([0; 4], 8128));
     let listener = TcpListener::bind(addr)
@@ -0,0 +1,427 @@
+//! Checks a contest for mistakes before it is deployed.
+//!
+//! Usage: `validate [--solutions <dir>] <contest>`
+//!
+//! `<contest>` is either a contest directory or a JSON file. If a solutions directory is given,
+//! the reference solution for each task is judged and must be Accepted on every test. Solutions
+//! are named after the task number, with the extension of the language they are written in (e.g.
+//! `1.cpp` for task 1 in a language whose filename is `submission.cpp`).
+
+use std::{env, fmt, path::Path, process::ExitCode, sync::Arc};
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use judge::{
+    contest::{Config, Kind, Task},
+    queue,
+    registry::{self, Loaded},
+    sandbox::ResourceLimits,
+    store::{self, Entry, Record},
+    submit::{Job, Verdict},
+};
+use tokio::fs;
+use uuid::Uuid;
+use yansi::Paint;
+
+const USAGE: &str = "usage: validate [--solutions <dir>] <contest>";
+
+/// Memory limits below this are too small for most languages' runtimes
+const MIN_MEMORY: u64 = 16 * 1024 * 1024;
+/// CPU time limits above this were probably given in milliseconds instead of seconds
+const MAX_CPU: f64 = 60.0;
+
+#[derive(Default)]
+struct Problems {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Problems {
+    fn error(&mut self, location: impl fmt::Display, message: impl fmt::Display) {
+        self.errors += 1;
+        println!("{}: {location}: {message}", "error".red().bold());
+    }
+
+    fn warning(&mut self, location: impl fmt::Display, message: impl fmt::Display) {
+        self.warnings += 1;
+        println!("{}: {location}: {message}", "warning".yellow().bold());
+    }
+}
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<ExitCode> {
+    dotenvy::dotenv().ok();
+    color_eyre::install()?;
+
+    let mut args = env::args().skip(1);
+    let (mut contest_path, mut solutions) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--solutions" => solutions = Some(args.next().ok_or_else(|| eyre!(USAGE))?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ if contest_path.is_none() => contest_path = Some(arg),
+            _ => bail!(USAGE),
+        }
+    }
+
+    let contest_path = Path::new(&contest_path.ok_or_else(|| eyre!(USAGE))?).canonicalize()?;
+    let solutions = solutions
+        .map(|dir| Path::new(&dir).canonicalize())
+        .transpose()?;
+
+    let contest = match registry::read(&contest_path).await {
+        Ok(Some(contest)) => contest,
+        Ok(None) => bail!("{} is not a contest", contest_path.display()),
+        Err(e) => {
+            println!("{}: {e:#}", "error".red().bold());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let name = contest_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("contest")
+        .to_owned();
+
+    let mut problems = Problems::default();
+    check_config(&contest.config, &mut problems);
+    for (idx, task) in contest.tasks.iter().enumerate() {
+        check_task(&contest.config, idx + 1, task, &mut problems);
+    }
+    if contest.tasks.is_empty() {
+        problems.error("contest", "has no tasks");
+    }
+
+    // helper programs and solutions are compiled and judged in a scratch directory
+    let cwd = env::current_dir()?;
+    let scratch = env::temp_dir().join(format!("judge-validate-{}", Uuid::new_v4()));
+    fs::create_dir(&scratch).await?;
+    env::set_current_dir(&scratch)?;
+
+    let result = match Loaded::new(&name, contest).await {
+        Ok(loaded) => match solutions {
+            Some(solutions) => check_solutions(&loaded, &name, &solutions, &mut problems).await,
+            None => Ok(()),
+        },
+        Err(e) => {
+            problems.error("helper programs", format!("{e:#}"));
+            Ok(())
+        }
+    };
+
+    env::set_current_dir(cwd)?;
+    fs::remove_dir_all(&scratch).await?;
+
+    result?;
+
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        contest_path.display(),
+        problems.errors,
+        problems.warnings
+    );
+
+    Ok(if problems.errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn check_config(config: &Config, problems: &mut Problems) {
+    check_limits("contest", &config.resource_limits, problems);
+
+    if config.languages.is_empty() {
+        problems.error("contest", "has no languages");
+    }
+
+    for (name, profile) in &config.seccomp_profiles {
+        if profile.trace {
+            problems.warning(
+                format!("seccomp profile {name}"),
+                "is in trace mode, so runs can make any syscall",
+            );
+        }
+    }
+
+    for (idx, language) in config.languages.iter().enumerate() {
+        let location = format!("language {}", language.name);
+
+        if language
+            .time_multiplier
+            .is_some_and(|multiplier| !(multiplier.is_finite() && multiplier > 0.0))
+        {
+            problems.error(&location, "time multiplier must be a positive number");
+        }
+
+        if config.languages[..idx]
+            .iter()
+            .any(|other| other.name == language.name)
+        {
+            problems.error(&location, "is defined more than once");
+        }
+
+        for (kind, command) in [
+            ("compile", language.compile.as_ref()),
+            ("run", Some(&language.run)),
+        ] {
+            let Some(command) = command else {
+                continue;
+            };
+
+            match command.first() {
+                None => problems.error(&location, format!("{kind} command is empty")),
+                // paths (e.g. `./submission`) are produced by compiling the submission
+                Some(program) if !program.contains('/') && which::which(program).is_err() => {
+                    problems.error(&location, format!("{kind} command `{program}` not found"))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+fn check_limits(location: &str, limits: &ResourceLimits, problems: &mut Problems) {
+    if !(limits.cpu.is_finite() && limits.cpu > 0.0) {
+        problems.error(location, "CPU time limit must be a positive number");
+    } else if limits.cpu > MAX_CPU {
+        problems.warning(
+            location,
+            format!(
+                "CPU time limit of {}s is unusually long (it is in seconds)",
+                limits.cpu
+            ),
+        );
+    }
+
+    if !(limits.cpu_tolerance.is_finite() && limits.cpu_tolerance >= 0.0) {
+        problems.error(location, "CPU time tolerance must be a non-negative number");
+    }
+
+    if limits.memory == 0 {
+        problems.error(location, "memory limit is zero");
+    } else if limits.memory < MIN_MEMORY {
+        problems.warning(
+            location,
+            format!(
+                "memory limit of {} bytes is too small for most languages (it is in bytes)",
+                limits.memory
+            ),
+        );
+    }
+
+    if limits.output == 0 {
+        problems.error(location, "output limit is zero");
+    }
+
+    if limits.memory_tolerance >= limits.memory {
+        problems.warning(
+            location,
+            "memory tolerance is not smaller than the memory limit",
+        );
+    }
+
+    match limits.wall_time {
+        Some(wall_time) if !(wall_time.is_finite() && wall_time > 0.0) => {
+            problems.error(location, "wall clock time limit must be a positive number")
+        }
+        Some(wall_time) if wall_time <= limits.cpu => problems.warning(
+            location,
+            "wall clock time limit is not longer than the CPU time limit",
+        ),
+        _ => {}
+    }
+}
+
+fn check_task(config: &Config, number: usize, task: &Task, problems: &mut Problems) {
+    let location = format!("task {number} ({})", task.name);
+
+    if task.answer.is_some() {
+        if !task.subtasks.is_empty() {
+            problems.warning(&location, "has an answer, so its tests are never used");
+        }
+        return;
+    }
+
+    if task.subtasks.is_empty() {
+        problems.error(&location, "has no subtasks");
+    }
+
+    // the contest's limits are already checked, so only overridden limits are checked again
+    if let Some(overrides) = &task.resource_limits {
+        check_limits(
+            &location,
+            &overrides.apply(config.resource_limits),
+            problems,
+        );
+    }
+
+    for (subtask_idx, subtask) in task.subtasks.iter().enumerate() {
+        if subtask.resource_limits.is_some() {
+            check_limits(
+                &format!("{location} subtask {}", subtask_idx + 1),
+                &task.resource_limits(config, subtask_idx),
+                problems,
+            );
+        }
+
+        if subtask.tests.is_empty() {
+            problems.error(
+                &location,
+                format!("subtask {} has no tests", subtask_idx + 1),
+            );
+        }
+
+        for (test_idx, test) in subtask.tests.iter().enumerate() {
+            if test.output.trim().is_empty() && task.checker.is_none() {
+                problems.warning(
+                    &location,
+                    format!(
+                        "test {}-{} has an empty expected output",
+                        subtask_idx + 1,
+                        test_idx + 1
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut programs = Vec::new();
+    if let Some(checker) = &task.checker {
+        if let Kind::Interactive { .. } = task.kind {
+            problems.warning(&location, "checker is ignored for interactive tasks");
+        }
+        programs.push(("checker", checker));
+    }
+    if let Kind::Interactive { interactor } = &task.kind {
+        programs.push(("interactor", interactor));
+    }
+
+    for (kind, program) in programs {
+        if config.language(&program.language).is_none() {
+            problems.error(
+                &location,
+                format!("{kind} is written in unknown language {}", program.language),
+            );
+        }
+    }
+}
+
+async fn check_solutions(
+    contest: &Arc<Loaded>,
+    name: &str,
+    solutions: &Path,
+    problems: &mut Problems,
+) -> color_eyre::Result<()> {
+    let mut files = Vec::new();
+    let mut read_dir = fs::read_dir(solutions)
+        .await
+        .wrap_err("failed to scan solutions directory")?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        files.push(entry.path());
+    }
+
+    for (idx, task) in contest.tasks.iter().enumerate() {
+        let number = idx + 1;
+        let location = format!("task {number} ({})", task.name);
+
+        if task.answer.is_some() {
+            continue;
+        }
+
+        let Some(solution) = files.iter().find(|file| {
+            file.file_stem().and_then(|stem| stem.to_str()) == Some(&number.to_string())
+        }) else {
+            problems.warning(&location, "no reference solution");
+            continue;
+        };
+
+        let Some(language) = contest.config.language_for(solution) else {
+            problems.error(
+                &location,
+                format!("no language for reference solution {}", solution.display()),
+            );
+            continue;
+        };
+
+        let record = judge_solution(contest, name, number, language, solution).await?;
+        let location = format!("{location}: reference solution {}", solution.display());
+
+        match (record.report, record.error) {
+            (_, Some(reason)) => problems.error(&location, format!("judge error: {reason}")),
+            (Some(report), None) if report.task == Verdict::CompileError => problems.error(
+                &location,
+                format!(
+                    "failed to compile:\n{}",
+                    record.compile_stderr.unwrap_or_default()
+                ),
+            ),
+            (Some(report), None) if report.task != Verdict::Accepted => {
+                for (subtask_idx, tests) in report.tests.iter().enumerate() {
+                    for (test_idx, test) in tests.iter().enumerate() {
+                        if test.verdict == Verdict::Accepted {
+                            continue;
+                        }
+
+                        let mut message = format!(
+                            "{:?} on test {}-{}",
+                            test.verdict,
+                            subtask_idx + 1,
+                            test_idx + 1
+                        );
+                        if let Some(mismatch) = &test.mismatch {
+                            message.push_str(&format!(
+                                " (line {}: expected {}, found {})",
+                                mismatch.line,
+                                mismatch.expected.as_deref().unwrap_or("end of output"),
+                                mismatch.found.as_deref().unwrap_or("end of output"),
+                            ));
+                        }
+                        if let Some(checker_message) = &test.message {
+                            message.push_str(&format!(": {checker_message}"));
+                        }
+
+                        problems.error(&location, message);
+                    }
+                }
+            }
+            (Some(_), None) => println!("{}: {location}", "accepted".green().bold()),
+            (None, None) => problems.error(&location, "judging did not finish"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn judge_solution(
+    contest: &Arc<Loaded>,
+    name: &str,
+    task: usize,
+    language: usize,
+    solution: &Path,
+) -> color_eyre::Result<Record> {
+    let id = Uuid::new_v4();
+    fs::create_dir_all(store::dir(id)).await?;
+    let code = fs::read_to_string(solution)
+        .await
+        .wrap_err_with(|| format!("failed to read {}", solution.display()))?;
+
+    let language_name = contest.config.languages[language].name.clone();
+    let record = Record::new(id, name.to_owned(), task, None, Some(language_name.clone()));
+    let entry = Entry::create(record).await?;
+    let job = Job {
+        id,
+        contest: name.to_owned(),
+        task,
+        language: language_name,
+        code,
+        version: contest.version().to_owned(),
+    };
+    queue::judge(job, contest.clone(), &entry).await;
+
+    store::record(id)
+        .await?
+        .ok_or_else(|| eyre!("record for {} is missing", solution.display()))
+}
@@ -0,0 +1,78 @@
+//! A judge worker, which judges submissions from the server's queue (see [`judge::worker`]).
+//!
+//! Each worker needs its own working directory, containing the same `contests` as the server (e.g.
+//! as a symlink), since that is where it compiles the helper programs and judges submissions.
+//! Workers load the contests at startup, and again whenever a job needs a version of its contest
+//! that they don't have (e.g. after the server reloads them).
+//!
+//! Configured with the environment variables:
+//!
+//! - `WORKER_TOKEN`: the server's worker token (required)
+//! - `WORKER_SERVER`: address of the server (`127.0.0.1:8129` by default)
+//! - `WORKER_JOBS`: number of submissions to judge at once (one per CPU core by default)
+//! - `COMPILE_CACHE_SIZE`: size limit of the worker's cache of compiled submissions in bytes (see
+//!   [`judge::cache`]), with 0 disabling it
+
+use std::{env, path::Path, sync::Arc};
+
+use color_eyre::eyre::{eyre, WrapErr};
+use judge::{cache, queue, registry, sandbox, worker, CONTESTS};
+use tokio::{fs, task::JoinSet};
+use tracing_error::ErrorLayer;
+use tracing_subscriber::{prelude::*, EnvFilter};
+use tracing_tree::HierarchicalLayer;
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    dotenvy::dotenv().ok();
+    color_eyre::install()?;
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(HierarchicalLayer::default().with_ansi(true))
+        .with(ErrorLayer::default())
+        .try_init()
+        .wrap_err("failed to initialize tracing")?;
+
+    let token = env::var("WORKER_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| eyre!("WORKER_TOKEN is not set"))?;
+    let addr = env::var("WORKER_SERVER").unwrap_or_else(|_| format!("127.0.0.1:{}", worker::PORT));
+    let jobs = match env::var("WORKER_JOBS") {
+        Ok(count) => count.parse().wrap_err("WORKER_JOBS must be a number")?,
+        Err(_) => queue::default_local_workers(),
+    };
+    let cache_size = match env::var("COMPILE_CACHE_SIZE") {
+        Ok(size) => size
+            .parse()
+            .wrap_err("COMPILE_CACHE_SIZE must be a number")?,
+        Err(_) => cache::DEFAULT_SIZE,
+    };
+
+    sandbox::probe().await;
+
+    // helper programs from previous runs
+    if Path::new("programs").is_dir() {
+        fs::remove_dir_all("programs").await?;
+    }
+
+    cache::init(cache_size)
+        .await
+        .wrap_err("failed to set up compile cache")?;
+
+    let contests = registry::load(Path::new(worker::CONTESTS_DIR)).await?;
+    CONTESTS.store(Arc::new(contests));
+
+    let (addr, token) = (Arc::<str>::from(addr), Arc::<str>::from(token));
+    let mut connections = JoinSet::new();
+    for _ in 0..jobs {
+        let (addr, token) = (addr.clone(), token.clone());
+        connections.spawn(async move { worker::work(&addr, &token).await });
+    }
+
+    while let Some(result) = connections.join_next().await {
+        result.wrap_err("worker crashed")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,381 @@
+//! Cache of compiled submissions, so that resubmitting the same code (e.g. after losing the
+//! connection while it was being judged) skips straight to testing.
+//!
+//! Entries are keyed by a hash of the code together with the language's name and compile command.
+//! An entry holds the compiler's output, and the files that compiling produced are kept in a
+//! directory named after its key in [`CACHE_DIR`]. Compiles that were stopped by a limit are
+//! never cached, as they might succeed another time. Once the entries take up more than the size
+//! limit given to [`init`], the least recently used ones are evicted.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use ahash::AHashMap;
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use tokio::{fs, io};
+use uuid::Uuid;
+
+use crate::{contest::Language, store};
+
+/// Directory that cached files are kept in (relative to the working directory)
+pub const CACHE_DIR: &str = "cache";
+
+/// Size limit if the `COMPILE_CACHE_SIZE` environment variable isn't set (bytes)
+pub const DEFAULT_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Identifies the compiled form of some code in a language
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Key(String);
+
+/// What the compiler printed and how it exited
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerOutput {
+    pub exit_code: i32,
+    pub stderr: String,
+}
+
+/// Names and modification times of the files in a directory at some point in time
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot(AHashMap<OsString, SystemTime>);
+
+#[derive(Debug)]
+struct Entry {
+    output: CompilerOutput,
+    /// Total size of the files and compiler output (bytes)
+    size: u64,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    /// Size limit (bytes), which disables caching if zero
+    limit: u64,
+    /// Total size of every entry (bytes)
+    size: u64,
+    entries: AHashMap<Key, Entry>,
+    /// Incremented every time an entry is used
+    clock: u64,
+}
+
+static INDEX: Lazy<Mutex<Index>> = Lazy::new(Default::default);
+
+impl Key {
+    pub fn new(language: &Language, code: &str) -> Self {
+        let fields = [language.name.as_str(), language.filename.as_str()]
+            .into_iter()
+            .chain(language.compile.iter().flatten().map(String::as_str))
+            .chain([code]);
+
+        let mut hasher = Sha256::new();
+        for field in fields {
+            // lengths are included, so that moving bytes between fields changes the hash
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+        Key(format!("{:x}", hasher.finalize()))
+    }
+
+    fn dir(&self) -> PathBuf {
+        Path::new(CACHE_DIR).join(&self.0)
+    }
+
+    /// A new directory name for the entry's files while they are being written or deleted
+    fn tmp_dir(&self) -> PathBuf {
+        Path::new(CACHE_DIR).join(format!("{}.{}", self.0, Uuid::new_v4()))
+    }
+}
+
+impl Snapshot {
+    pub async fn take(dir: &Path) -> io::Result<Self> {
+        let mut files = AHashMap::new();
+        let mut read_dir = fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            files.insert(entry.file_name(), entry.metadata().await?.modified()?);
+        }
+        Ok(Snapshot(files))
+    }
+
+    /// Whether a file was created or modified since the snapshot was taken
+    fn changed(&self, name: &OsStr, modified: SystemTime) -> bool {
+        self.0.get(name) != Some(&modified)
+    }
+}
+
+impl Index {
+    /// Removes the least recently used entries until the cache fits in its size limit, returning
+    /// the keys of the entries whose files need deleting
+    fn evict(&mut self) -> Vec<Key> {
+        let mut evicted = Vec::new();
+        while self.size > self.limit {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+
+            let entry = self.entries.remove(&key).unwrap();
+            self.size -= entry.size;
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// Clears out the cache directory and sets the size limit (bytes), with zero disabling the cache
+///
+/// Caching is disabled until this is called.
+pub async fn init(limit: u64) -> io::Result<()> {
+    if Path::new(CACHE_DIR).is_dir() {
+        fs::remove_dir_all(CACHE_DIR).await?;
+    }
+    fs::create_dir(CACHE_DIR).await?;
+
+    *INDEX.lock().unwrap() = Index {
+        limit,
+        ..Default::default()
+    };
+    Ok(())
+}
+
+/// Copies the files from compiling the code into `dir`, returning the compiler's output if it was
+/// cached
+pub async fn restore(key: &Key, dir: &Path) -> Option<CompilerOutput> {
+    let output = {
+        let mut index = INDEX.lock().unwrap();
+        index.clock += 1;
+        let clock = index.clock;
+        let entry = index.entries.get_mut(key)?;
+        entry.last_used = clock;
+        entry.output.clone()
+    };
+
+    // the entry might have been evicted since, in which case the code is compiled again
+    let restored = async {
+        let names = list(&key.dir()).await?;
+        copy_files(&key.dir(), dir, &names).await
+    };
+    if let Err(e) = restored.await {
+        tracing::warn!("failed to restore cached compile: {e}");
+        return None;
+    }
+
+    Some(output)
+}
+
+/// Caches the compiler's output along with the files that compiling created or modified in `dir`
+/// (if it succeeded), evicting the least recently used entries if the cache gets too big
+pub async fn store(key: &Key, dir: &Path, before: &Snapshot, output: CompilerOutput) {
+    let limit = {
+        let index = INDEX.lock().unwrap();
+        if index.limit == 0 || index.entries.contains_key(key) {
+            return;
+        }
+        index.limit
+    };
+
+    // entries are written under a temporary name, so that they're never seen half written
+    let tmp = key.tmp_dir();
+    let saved = async {
+        let names = if output.exit_code == 0 {
+            match new_files(dir, before).await? {
+                Some(names) => names,
+                None => return Ok(None),
+            }
+        } else {
+            Vec::new()
+        };
+
+        fs::create_dir(&tmp).await?;
+        let size = copy_files(dir, &tmp, &names).await? + output.stderr.len() as u64;
+        if size > limit {
+            return Ok(None);
+        }
+
+        // fails if another submission with the same code got there first
+        fs::rename(&tmp, key.dir()).await?;
+        Ok::<_, io::Error>(Some(size))
+    };
+
+    let size = match saved.await {
+        Ok(Some(size)) => size,
+        Ok(None) => {
+            tracing::trace!("not caching compile");
+            fs::remove_dir_all(&tmp).await.ok();
+            return;
+        }
+        Err(e) => {
+            tracing::debug!("failed to cache compile: {e}");
+            fs::remove_dir_all(&tmp).await.ok();
+            return;
+        }
+    };
+
+    let evicted: Vec<_> = {
+        let mut index = INDEX.lock().unwrap();
+        index.clock += 1;
+        let entry = Entry {
+            output,
+            size,
+            last_used: index.clock,
+        };
+        // an identical compile that failed might have been cached in the meantime
+        if let Some(replaced) = index.entries.insert(key.clone(), entry) {
+            index.size -= replaced.size;
+        }
+        index.size += size;
+
+        // evicted files are moved out of the way before the index is unlocked, as deleting them
+        // afterwards could delete the files of the same code if it were cached again in between
+        index
+            .evict()
+            .into_iter()
+            .filter_map(|key| {
+                let tmp = key.tmp_dir();
+                match std::fs::rename(key.dir(), &tmp) {
+                    Ok(()) => Some(tmp),
+                    Err(e) => {
+                        tracing::warn!("failed to evict cached compile: {e}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    };
+
+    for tmp in evicted {
+        if let Err(e) = fs::remove_dir_all(tmp).await {
+            tracing::warn!("failed to evict cached compile: {e}");
+        }
+    }
+}
+
+/// Names of the files in `dir` that were created or modified since `before` (e.g. by compiling
+/// it again when rejudging), or `None` if any of them isn't a regular file
+async fn new_files(dir: &Path, before: &Snapshot) -> io::Result<Option<Vec<OsString>>> {
+    let mut names = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name();
+        let metadata = entry.metadata().await?;
+        // the store writes to the submission directory while it's being compiled
+        if !before.changed(&name, metadata.modified()?) || store::is_store_file(&name) {
+            continue;
+        }
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        names.push(name);
+    }
+    Ok(Some(names))
+}
+
+async fn list(dir: &Path) -> io::Result<Vec<OsString>> {
+    let mut names = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        names.push(entry.file_name());
+    }
+    Ok(names)
+}
+
+/// Copies files from one directory to another, returning their total size
+async fn copy_files(from: &Path, to: &Path, names: &[OsString]) -> io::Result<u64> {
+    let mut size = 0;
+    for name in names {
+        // permissions are copied too, so executables stay executable
+        size += fs::copy(from.join(name), to.join(name)).await?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, last_used: u64) -> Entry {
+        Entry {
+            output: CompilerOutput {
+                exit_code: 0,
+                stderr: String::new(),
+            },
+            size,
+            last_used,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let key = |name: &str| Key(name.to_owned());
+        let mut index = Index {
+            limit: 9,
+            size: 14,
+            entries: AHashMap::from_iter([
+                (key("a"), entry(4, 3)),
+                (key("b"), entry(4, 1)),
+                (key("c"), entry(6, 2)),
+            ]),
+            clock: 3,
+        };
+
+        // evicting `b` alone isn't enough, so `c` goes too, even though `a` is smaller
+        assert_eq!(index.evict(), vec![key("b"), key("c")]);
+        assert_eq!(index.size, 4);
+        assert!(index.entries.contains_key(&key("a")));
+        assert!(index.evict().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restores_until_evicted() {
+        let output = CompilerOutput {
+            exit_code: 0,
+            stderr: "warning".to_owned(),
+        };
+        // room for one entry of a 5 byte file and the compiler output, but not two
+        init(20).await.unwrap();
+
+        let submissions = std::env::temp_dir().join(format!("cache-test-{}", Uuid::new_v4()));
+        let compile = |name: &str| {
+            let dir = submissions.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("main"), name.repeat(5)).unwrap();
+            dir
+        };
+        let restore_into = |name: &str| {
+            let dir = submissions.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        };
+        let (a, b) = (Key("a".to_owned()), Key("b".to_owned()));
+
+        let dir = compile("a");
+        store(&a, &dir, &Snapshot::default(), output.clone()).await;
+        let dir = restore_into("a-again");
+        assert_eq!(restore(&a, &dir).await, Some(output.clone()));
+        assert_eq!(std::fs::read_to_string(dir.join("main")).unwrap(), "aaaaa");
+
+        let dir = compile("b");
+        store(&b, &dir, &Snapshot::default(), output.clone()).await;
+        assert_eq!(restore(&a, &restore_into("a-evicted")).await, None);
+        let dir = restore_into("b-again");
+        assert_eq!(restore(&b, &dir).await, Some(output));
+        assert_eq!(std::fs::read_to_string(dir.join("main")).unwrap(), "bbbbb");
+
+        // the evicted entry's files are deleted, with nothing left under another name
+        assert_eq!(
+            list(Path::new(CACHE_DIR)).await.unwrap(),
+            vec![OsString::from("b")]
+        );
+
+        std::fs::remove_dir_all(submissions).unwrap();
+        std::fs::remove_dir_all(CACHE_DIR).unwrap();
+        *INDEX.lock().unwrap() = Index::default();
+    }
+}
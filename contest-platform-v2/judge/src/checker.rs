@@ -0,0 +1,63 @@
+//! Custom checkers.
+//!
+//! The checker is invoked as `<run command> <input> <expected> <output>`, where each argument is
+//! a path to a file containing the test input, the expected output and the contestant's output
+//! respectively. A partial score is written to stdout, and anything written to stderr is
+//! included in the test report as the checker message.
+
+use std::path::Path;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+
+use crate::{
+    contest::{Config, Program, Test},
+    program::{Outcome, Scratch},
+    sandbox::{run, Profile, ResourceLimits},
+};
+
+/// Runs the checker in `dir` against the contestant's output
+pub async fn check(
+    dir: &Path,
+    config: &Config,
+    checker: &Program,
+    resource_limits: ResourceLimits,
+    test: &Test,
+    output: &[u8],
+) -> color_eyre::Result<Outcome> {
+    let language = config
+        .language(&checker.language)
+        .ok_or_else(|| eyre!("unsupported checker language: {}", checker.language))?;
+    let profile = Profile::Run(resource_limits, language.syscall_filter(config)?);
+
+    let (scratch, files) = Scratch::new(
+        dir,
+        &[
+            ("input", test.input.as_bytes()),
+            ("expected", test.output.as_bytes()),
+            ("output", output),
+        ],
+    )
+    .await?;
+
+    let command = [language.run.clone(), files].concat();
+    let result = run(dir, &command, &[], profile).await;
+    scratch.remove().await;
+    let output = result.wrap_err("failed to execute checker")?;
+    if let Some(syscall) = output.denied_syscall() {
+        bail!("checker made a restricted syscall: {syscall}");
+    }
+
+    let message = output
+        .stderr_utf8()
+        .ok()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned);
+
+    Outcome::new(
+        output.exit_status(),
+        output.stdout_utf8().unwrap_or_default(),
+        message,
+    )
+    .wrap_err("checker failed")
+}
@@ -0,0 +1,303 @@
+//! Output comparison
+//!
+//! `contest-platform-v1/src/judge/compare.rs` has a cut-down copy of this, which must follow the
+//! same rules so that the two judges agree on verdicts.
+
+use std::iter;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of characters of a token that are kept in a [`Mismatch`]
+const TOKEN_LIMIT: usize = 64;
+
+/// How the output of a submission is compared to the expected output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum Comparison {
+    /// Outputs must be identical, apart from leading and trailing whitespace
+    #[default]
+    Exact,
+    /// Outputs must contain the same whitespace-separated tokens
+    Tokens,
+    /// Outputs must contain the same whitespace-separated tokens, ignoring case
+    CaseInsensitive,
+    /// Outputs must contain the same whitespace-separated tokens, but numeric tokens only need to
+    /// be within either the absolute or relative error of the expected value
+    Numeric { absolute: f64, relative: f64 },
+}
+
+/// Location of the first difference between the output and the expected output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Mismatch {
+    /// Index of the first differing token (starting from 1)
+    pub token: usize,
+    /// Line of the output containing the differing token (starting from 1)
+    pub line: usize,
+    /// The expected token, or `None` if the output has too many tokens
+    pub expected: Option<String>,
+    /// The token found in the output, or `None` if the output ended early
+    pub found: Option<String>,
+}
+
+struct Token<'a> {
+    line: usize,
+    offset: usize,
+    text: &'a str,
+}
+
+impl Comparison {
+    pub fn compare(&self, expected: &str, output: &str) -> Result<(), Mismatch> {
+        if let Comparison::Exact = self {
+            return compare_exact(expected.trim(), output.trim());
+        }
+
+        let (mut expected_tokens, mut output_tokens) = (tokens(expected), tokens(output));
+
+        for index in 1.. {
+            match (expected_tokens.next(), output_tokens.next()) {
+                (None, None) => break,
+                (Some(e), Some(o)) if self.matches(e.text, o.text) => continue,
+                (e, o) => return Err(Mismatch::new(index, e, o, output)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, expected: &str, found: &str) -> bool {
+        match *self {
+            Comparison::Exact | Comparison::Tokens => expected == found,
+            Comparison::CaseInsensitive => expected.to_lowercase() == found.to_lowercase(),
+            Comparison::Numeric { absolute, relative } => {
+                match (expected.parse::<f64>(), found.parse::<f64>()) {
+                    // non-finite values (e.g. `nan`) are compared as text, as NaN never equals NaN
+                    (Ok(e), Ok(f)) if e.is_finite() && f.is_finite() => {
+                        let error = (e - f).abs();
+                        e == f || error <= absolute || error <= relative * e.abs()
+                    }
+                    _ => expected == found,
+                }
+            }
+        }
+    }
+}
+
+fn compare_exact(expected: &str, output: &str) -> Result<(), Mismatch> {
+    if expected == output {
+        return Ok(());
+    }
+
+    let offset = expected
+        .bytes()
+        .zip(output.bytes())
+        .take_while(|(e, o)| e == o)
+        .count();
+
+    // the first token that contains or follows the first differing byte, or that ends there if
+    // the other string carries on with the same token (e.g. `12` and `123`)
+    let find = |s, other: &str| {
+        let extended = other
+            .get(offset..)
+            .and_then(|rest| rest.chars().next())
+            .is_some_and(|c| !c.is_whitespace());
+        tokens(s).enumerate().find(|(_, token)| {
+            let end = token.offset + token.text.len();
+            end > offset || (end == offset && extended)
+        })
+    };
+
+    let (index, found) = match find(output, expected) {
+        Some((idx, token)) => (idx + 1, Some(token)),
+        None => (tokens(output).count() + 1, None),
+    };
+    let expected = find(expected, output).map(|(_, token)| token);
+
+    Err(Mismatch::new(index, expected, found, output))
+}
+
+impl Mismatch {
+    fn new(index: usize, expected: Option<Token>, found: Option<Token>, output: &str) -> Self {
+        let truncate = |token: Token| token.text.chars().take(TOKEN_LIMIT).collect();
+
+        Mismatch {
+            token: index,
+            line: found
+                .as_ref()
+                .map(|token| token.line)
+                .unwrap_or_else(|| output.lines().count().max(1)),
+            expected: expected.map(truncate),
+            found: found.map(truncate),
+        }
+    }
+}
+
+fn tokens(s: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut chars = s.char_indices().peekable();
+    let mut line = 1;
+
+    iter::from_fn(move || {
+        while let Some(&(_, c)) = chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            } else if c == '\n' {
+                line += 1;
+            }
+            chars.next();
+        }
+
+        let (start, _) = *chars.peek()?;
+        let mut end = s.len();
+        while let Some(&(idx, c)) = chars.peek() {
+            if c.is_whitespace() {
+                end = idx;
+                break;
+            }
+            chars.next();
+        }
+
+        Some(Token {
+            line,
+            offset: start,
+            text: &s[start..end],
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUMERIC: Comparison = Comparison::Numeric {
+        absolute: 0.5,
+        relative: 0.25,
+    };
+
+    fn mismatch(
+        token: usize,
+        line: usize,
+        expected: Option<&str>,
+        found: Option<&str>,
+    ) -> Mismatch {
+        Mismatch {
+            token,
+            line,
+            expected: expected.map(str::to_owned),
+            found: found.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn exact() {
+        let exact = Comparison::Exact;
+        assert_eq!(exact.compare("1 2\n3\n", "  1 2\n3"), Ok(()));
+        assert_eq!(
+            exact.compare("1 2", "1  2"),
+            Err(mismatch(2, 1, Some("2"), Some("2")))
+        );
+        assert_eq!(
+            exact.compare("1 2\n3 4\n", "1 2\n3 5\n"),
+            Err(mismatch(4, 2, Some("4"), Some("5")))
+        );
+        assert_eq!(
+            exact.compare("1 2", "1 23"),
+            Err(mismatch(2, 1, Some("2"), Some("23")))
+        );
+        assert_eq!(
+            exact.compare("1 23", "1 2"),
+            Err(mismatch(2, 1, Some("23"), Some("2")))
+        );
+        assert_eq!(
+            exact.compare("1 2 3", "1 2"),
+            Err(mismatch(3, 1, Some("3"), None))
+        );
+        assert_eq!(
+            exact.compare("1 2", "1 2\n3"),
+            Err(mismatch(3, 2, None, Some("3")))
+        );
+        assert_eq!(exact.compare("1", ""), Err(mismatch(1, 1, Some("1"), None)));
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = Comparison::Tokens;
+        assert_eq!(tokens.compare("1 2\n3", "1\n2   3 \n"), Ok(()));
+        assert_eq!(
+            tokens.compare("1 2\n3", "1\n2 4"),
+            Err(mismatch(3, 2, Some("3"), Some("4")))
+        );
+        assert_eq!(
+            tokens.compare("1 2 3", "1\n2\n"),
+            Err(mismatch(3, 2, Some("3"), None))
+        );
+        assert_eq!(
+            tokens.compare("1 2", "1 2 3"),
+            Err(mismatch(3, 1, None, Some("3")))
+        );
+        assert_eq!(
+            tokens.compare("yes", "YES"),
+            Err(mismatch(1, 1, Some("yes"), Some("YES")))
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let case_insensitive = Comparison::CaseInsensitive;
+        assert_eq!(case_insensitive.compare("Yes NO", "yes\nno"), Ok(()));
+        assert_eq!(
+            case_insensitive.compare("yes yes", "YES no"),
+            Err(mismatch(2, 1, Some("yes"), Some("no")))
+        );
+    }
+
+    #[test]
+    fn numeric() {
+        for (expected, found, matches) in [
+            ("1", "1.0", true),
+            ("1", "1.5", true),
+            ("1", "0.5", true),
+            ("1", "1.75", false),
+            // the relative error is relative to the expected value
+            ("4", "5", true),
+            ("4", "3", true),
+            ("-4", "-5", true),
+            ("4", "5.5", false),
+            ("6", "4.5", true),
+            ("4.5", "6", false),
+            ("abc", "abc", true),
+            ("abc", "ABC", false),
+            ("1", "one", false),
+            ("nan", "nan", true),
+            ("NaN", "nan", false),
+            ("nan", "1", false),
+            ("inf", "inf", true),
+            ("inf", "-inf", false),
+            ("inf", "1e999", false),
+        ] {
+            let result = NUMERIC.compare(expected, found);
+            assert_eq!(
+                result.is_ok(),
+                matches,
+                "{expected} and {found}: {result:?}"
+            );
+        }
+
+        assert_eq!(
+            NUMERIC.compare("1 2\n3", "1.1 2.2\n4"),
+            Err(mismatch(3, 2, Some("3"), Some("4")))
+        );
+        assert_eq!(
+            NUMERIC.compare("1 2", "1"),
+            Err(mismatch(2, 1, Some("2"), None))
+        );
+    }
+
+    #[test]
+    fn truncates_long_tokens() {
+        let long = "9".repeat(TOKEN_LIMIT * 2);
+        let Err(mismatch) = Comparison::Tokens.compare("1", &long) else {
+            panic!("outputs matched");
+        };
+        assert_eq!(mismatch.found, Some("9".repeat(TOKEN_LIMIT)));
+    }
+}
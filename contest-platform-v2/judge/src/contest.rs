@@ -1,11 +1,23 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compare::Comparison,
+    sandbox::{
+        CompileLimits, ResourceLimits, SeccompError, SeccompProfile, SyscallFilter, DEFAULT_PROFILE,
+    },
+    scoring::ScoringModel,
+};
+
+pub use load::LoadError;
 
-use crate::sandbox::ResourceLimits;
+mod load;
 
 // NOTE: not all fields are used by the judge server, but are included to generate a JSON Schema
 
-#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Contest {
     pub name: String,
@@ -18,56 +30,207 @@ pub struct Contest {
     pub config: Config,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Task {
     pub name: String,
     pub difficulty: Difficulty,
     pub answer: Option<String>,
     pub page: String,
     pub subtasks: Vec<Subtask>,
+    /// Custom checker, for tasks that accept more than one correct output (batch tasks only)
+    pub checker: Option<Program>,
+    #[serde(default)]
+    pub kind: Kind,
+    /// Overrides the contest's output comparison mode (which also applies to answers)
+    pub comparison: Option<Comparison>,
+    /// Overrides the contest's resource limits
+    pub resource_limits: Option<LimitOverrides>,
+}
+
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Kind {
+    /// The test input is given on stdin, and the output is read from stdout
+    #[default]
+    Batch,
+    /// The submission communicates with an interactor over stdin and stdout
+    Interactive { interactor: Program },
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub enum Difficulty {
     Easy,
     Medium,
     Hard,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Subtask {
     pub tests: Vec<Test>,
+    /// Overrides the contest's subtask score
+    pub score: Option<u32>,
+    /// Overrides the task's resource limits
+    pub resource_limits: Option<LimitOverrides>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct Test {
     pub input: String,
     pub output: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Scoring {
     pub answer_score: u32,
+    /// Points for an accepted task, on top of the points for its subtasks and tests
+    #[serde(default)]
+    pub task_score: u32,
     pub test_score: u32,
     pub subtask_score: u32,
+    #[serde(default)]
+    pub model: ScoringModel,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub skip_count: u8,
     pub resource_limits: ResourceLimits,
+    #[serde(default)]
+    pub compile_limits: CompileLimits,
+    #[serde(default)]
+    pub comparison: Comparison,
     #[serde(alias = "language")]
     pub languages: Vec<Language>,
+    /// Named seccomp profiles for the languages to use, in addition to the built-in `default`
+    /// profile (which a profile of the same name replaces)
+    #[serde(default)]
+    pub seccomp_profiles: BTreeMap<String, SeccompProfile>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Language {
     pub name: String,
     pub filename: String,
     pub compile: Option<Vec<String>>,
     pub run: Vec<String>,
+    /// Multiplier for the CPU time limit and its tolerance, and the wall clock time limit (e.g. 3
+    /// for Python), defaults to 1
+    pub time_multiplier: Option<f64>,
+    /// Overrides the contest's compile limits (e.g. for compilers that run on the JVM)
+    pub compile_limits: Option<CompileLimits>,
+    /// Name of the seccomp profile for running programs in this language, defaults to `default`
+    pub seccomp_profile: Option<String>,
+}
+
+/// Resource limits that replace the ones inherited from the contest or task (if present)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LimitOverrides {
+    /// CPU time (seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<f64>,
+    /// CPU time tolerance (seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_tolerance: Option<f64>,
+    /// Memory usage (bytes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    /// Memory usage tolerance (bytes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_tolerance: Option<u64>,
+    /// Wall clock time (seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wall_time: Option<f64>,
+    /// Size of stdout, stderr and each file written (bytes)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<u64>,
+}
+
+/// A helper program (e.g. a checker) written in one of the contest's languages
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Program {
+    /// Name of the language, which must be one of the contest's languages
+    pub language: String,
+    pub source: String,
+}
+
+impl Task {
+    pub fn comparison(&self, config: &Config) -> Comparison {
+        self.comparison.unwrap_or(config.comparison)
+    }
+
+    /// Resource limits for a subtask (numbered from 0), before applying any language multiplier
+    pub fn resource_limits(&self, config: &Config, subtask: usize) -> ResourceLimits {
+        [
+            &self.resource_limits,
+            &self.subtasks[subtask].resource_limits,
+        ]
+        .into_iter()
+        .flatten()
+        .fold(config.resource_limits, |limits, overrides| {
+            overrides.apply(limits)
+        })
+    }
+}
+
+impl Language {
+    pub fn compile_limits(&self, config: &Config) -> CompileLimits {
+        self.compile_limits.unwrap_or(config.compile_limits)
+    }
+
+    /// Seccomp filter for running programs in this language, compiled the first time it's needed
+    pub fn syscall_filter(&self, config: &Config) -> Result<Arc<SyscallFilter>, SeccompError> {
+        let profile = self.seccomp_profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+        SyscallFilter::new(profile, &config.seccomp_profiles)
+    }
+
+    /// Scales the time limits by the language's time multiplier
+    pub fn scale(&self, resource_limits: ResourceLimits) -> ResourceLimits {
+        let multiplier = self.time_multiplier.unwrap_or(1.0);
+        ResourceLimits {
+            cpu: resource_limits.cpu * multiplier,
+            cpu_tolerance: resource_limits.cpu_tolerance * multiplier,
+            wall_time: resource_limits.wall_time.map(|t| t * multiplier),
+            ..resource_limits
+        }
+    }
+}
+
+impl LimitOverrides {
+    pub fn apply(&self, resource_limits: ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            cpu: self.cpu.unwrap_or(resource_limits.cpu),
+            cpu_tolerance: self.cpu_tolerance.unwrap_or(resource_limits.cpu_tolerance),
+            memory: self.memory.unwrap_or(resource_limits.memory),
+            memory_tolerance: self
+                .memory_tolerance
+                .unwrap_or(resource_limits.memory_tolerance),
+            // a different CPU time limit would make the inherited wall clock time limit stale
+            wall_time: self.wall_time.or(match self.cpu {
+                Some(_) => None,
+                None => resource_limits.wall_time,
+            }),
+            output: self.output.unwrap_or(resource_limits.output),
+        }
+    }
+}
+
+impl Config {
+    pub fn language(&self, name: &str) -> Option<&Language> {
+        self.languages.iter().find(|lang| lang.name == name)
+    }
+
+    /// Index of the language that a source file is written in, going by its extension
+    pub fn language_for(&self, source: &Path) -> Option<usize> {
+        self.languages
+            .iter()
+            .position(|lang| Path::new(&lang.filename).extension() == source.extension())
+    }
 }
 
 impl Contest {
@@ -75,3 +238,72 @@ impl Contest {
         serde_json::from_str(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ResourceLimits = ResourceLimits {
+        cpu: 1.0,
+        cpu_tolerance: 0.1,
+        memory: 256_000_000,
+        memory_tolerance: 1_000_000,
+        wall_time: Some(3.0),
+        output: 1_000_000,
+    };
+
+    #[test]
+    fn overrides() {
+        let memory = LimitOverrides {
+            memory: Some(512_000_000),
+            ..LimitOverrides::default()
+        };
+        assert_eq!(
+            memory.apply(LIMITS),
+            ResourceLimits {
+                memory: 512_000_000,
+                ..LIMITS
+            }
+        );
+
+        // the inherited wall clock time limit only applies to the inherited CPU time limit
+        let cpu = LimitOverrides {
+            cpu: Some(2.0),
+            ..LimitOverrides::default()
+        };
+        assert_eq!(
+            cpu.apply(LIMITS),
+            ResourceLimits {
+                cpu: 2.0,
+                wall_time: None,
+                ..LIMITS
+            }
+        );
+    }
+
+    #[test]
+    fn scales_time_limits() {
+        let language = Language {
+            name: "Python 3".to_owned(),
+            filename: "submission.py".to_owned(),
+            compile: None,
+            run: vec!["python3".to_owned(), "submission.py".to_owned()],
+            time_multiplier: Some(3.0),
+            compile_limits: None,
+            seccomp_profile: None,
+        };
+
+        let scaled = language.scale(LIMITS);
+        assert_eq!(scaled.cpu, 3.0);
+        assert!((scaled.cpu_tolerance - 0.3).abs() < 1e-9);
+        assert_eq!(scaled.wall_time, Some(9.0));
+        assert_eq!(scaled.memory, LIMITS.memory);
+        assert_eq!(scaled.output, LIMITS.output);
+
+        let native = Language {
+            time_multiplier: None,
+            ..language
+        };
+        assert_eq!(native.scale(LIMITS), LIMITS);
+    }
+}
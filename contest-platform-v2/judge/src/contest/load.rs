@@ -0,0 +1,421 @@
+//! Loader for contests in the on-disk directory format:
+//!
+//! ```text
+//! contest/
+//! ├── contest.md        contest page, with the contest settings as TOML front matter
+//! ├── 01-task/
+//! │   ├── task.md       task page, with the task settings as TOML front matter
+//! │   ├── 1/            tests for subtask 1
+//! │   │   ├── subtask.toml  subtask settings (optional)
+//! │   │   ├── 1.in
+//! │   │   ├── 1.out
+//! │   │   └── ...
+//! │   └── 2/
+//! └── 02-task/
+//! ```
+//!
+//! Tasks are ordered by the number at the start of their directory name, and subtasks by their
+//! number. Tests must be numbered consecutively from 1. Tasks with an answer have no tests.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{html, Options, Parser};
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{Contest, Subtask, Task, Test};
+
+const FRONT_MATTER_DELIMITER: &str = "+++";
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}: missing TOML front matter", .0.display())]
+    MissingFrontMatter(PathBuf),
+    #[error("{}: unterminated TOML front matter", .0.display())]
+    UnterminatedFrontMatter(PathBuf),
+    #[error("{}: {source}", path.display())]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("{}: directory name must start with the task number", .0.display())]
+    UnnumberedTask(PathBuf),
+    #[error("{}: directory name must be the subtask number", .0.display())]
+    UnnumberedSubtask(PathBuf),
+    #[error("{}: test {test} is missing (tests must be numbered from 1)", subtask.display())]
+    MissingTest { subtask: PathBuf, test: usize },
+    #[error("missing {} for test input {}", output.display(), input.display())]
+    MissingOutput { input: PathBuf, output: PathBuf },
+    #[error("missing {} for expected output {}", input.display(), output.display())]
+    MissingInput { input: PathBuf, output: PathBuf },
+}
+
+impl Contest {
+    /// Loads a contest from a directory in the on-disk format
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let dir = dir.as_ref();
+
+        let tasks = numbered_dirs(dir, leading_number, LoadError::UnnumberedTask)?
+            .iter()
+            .map(|task_dir| load_task(task_dir))
+            .collect::<Result<_, _>>()?;
+
+        let path = dir.join("contest.md");
+        let mut contest: Contest = load_page(&path, "tasks")?;
+        contest.tasks = tasks;
+
+        Ok(contest)
+    }
+}
+
+fn load_task(dir: &Path) -> Result<Task, LoadError> {
+    let mut task: Task = load_page(&dir.join("task.md"), "subtasks")?;
+
+    if task.answer.is_none() {
+        task.subtasks = numbered_dirs(dir, |name| name.parse().ok(), LoadError::UnnumberedSubtask)?
+            .iter()
+            .map(|subtask_dir| load_subtask(subtask_dir))
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(task)
+}
+
+fn load_subtask(dir: &Path) -> Result<Subtask, LoadError> {
+    let mut files: BTreeMap<usize, (Option<PathBuf>, Option<PathBuf>)> = BTreeMap::new();
+
+    for path in read_dir(dir)? {
+        let is_input = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("in") => true,
+            Some("out") => false,
+            _ => continue,
+        };
+
+        let Some(test) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            continue;
+        };
+
+        let (input, output) = files.entry(test).or_default();
+        if is_input {
+            *input = Some(path);
+        } else {
+            *output = Some(path);
+        }
+    }
+
+    let mut tests = Vec::with_capacity(files.len());
+    for (expected, (test, files)) in (1..).zip(files) {
+        if test != expected {
+            return Err(LoadError::MissingTest {
+                subtask: dir.to_owned(),
+                test: expected,
+            });
+        }
+
+        let (input, output) = match files {
+            (Some(input), Some(output)) => (input, output),
+            (Some(input), None) => {
+                let output = input.with_extension("out");
+                return Err(LoadError::MissingOutput { input, output });
+            }
+            (None, Some(output)) => {
+                let input = output.with_extension("in");
+                return Err(LoadError::MissingInput { input, output });
+            }
+            (None, None) => unreachable!("entries are only created for test files"),
+        };
+
+        tests.push(Test {
+            input: read_to_string(&input)?,
+            output: read_to_string(&output)?,
+        });
+    }
+
+    let path = dir.join("subtask.toml");
+    let mut subtask = if path.is_file() {
+        let mut table: toml::Table =
+            toml::from_str(&read_to_string(&path)?).map_err(|source| LoadError::Toml {
+                path: path.clone(),
+                source,
+            })?;
+        table.insert("tests".to_owned(), toml::Value::Array(Vec::new()));
+
+        Subtask::deserialize(toml::Value::Table(table))
+            .map_err(|source| LoadError::Toml { path, source })?
+    } else {
+        Subtask {
+            tests: Vec::new(),
+            score: None,
+            resource_limits: None,
+        }
+    };
+
+    subtask.tests = tests;
+    Ok(subtask)
+}
+
+/// Deserializes the front matter of a page, adding the rendered page and leaving the `children`
+/// (tasks or subtasks, which are loaded separately) empty
+fn load_page<T: for<'de> Deserialize<'de>>(path: &Path, children: &str) -> Result<T, LoadError> {
+    let src = read_to_string(path)?;
+
+    let (front_matter, markdown) = src
+        .trim()
+        .strip_prefix(FRONT_MATTER_DELIMITER)
+        .ok_or_else(|| LoadError::MissingFrontMatter(path.to_owned()))?
+        .split_once(FRONT_MATTER_DELIMITER)
+        .ok_or_else(|| LoadError::UnterminatedFrontMatter(path.to_owned()))?;
+
+    let toml_error = |source| LoadError::Toml {
+        path: path.to_owned(),
+        source,
+    };
+
+    let mut table: toml::Table = toml::from_str(front_matter).map_err(toml_error)?;
+    table.insert("page".to_owned(), render(markdown.trim()).into());
+    table.insert(children.to_owned(), toml::Value::Array(Vec::new()));
+
+    T::deserialize(toml::Value::Table(table)).map_err(toml_error)
+}
+
+fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_MATH;
+    let mut html = String::new();
+    html::push_html(&mut html, Parser::new_ext(markdown, options));
+    html
+}
+
+/// Subdirectories of `dir` that are numbered according to `number`, in order
+fn numbered_dirs(
+    dir: &Path,
+    number: impl Fn(&str) -> Option<u32>,
+    unnumbered: fn(PathBuf) -> LoadError,
+) -> Result<Vec<PathBuf>, LoadError> {
+    let mut dirs = Vec::new();
+
+    for path in read_dir(dir)? {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Err(unnumbered(path));
+        };
+
+        // skip hidden directories (e.g. `.git`)
+        if !path.is_dir() || name.starts_with('.') {
+            continue;
+        }
+
+        match number(name) {
+            Some(n) => dirs.push((n, path)),
+            None => return Err(unnumbered(path)),
+        }
+    }
+
+    dirs.sort_unstable();
+    Ok(dirs.into_iter().map(|(_, path)| path).collect())
+}
+
+fn leading_number(name: &str) -> Option<u32> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    name[..end].parse().ok()
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, LoadError> {
+    let io_error = |source| LoadError::Io {
+        path: dir.to_owned(),
+        source,
+    };
+
+    fs::read_dir(dir)
+        .map_err(io_error)?
+        .map(|entry| entry.map(|entry| entry.path()).map_err(io_error))
+        .collect()
+}
+
+fn read_to_string(path: &Path) -> Result<String, LoadError> {
+    fs::read_to_string(path).map_err(|source| LoadError::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    const CONTEST: &str = r#"+++
+name = "Test"
+duration = 3600
+submission-cooldown = 60
+
+[scoring]
+answer-score = 100
+subtask-score = 50
+test-score = 5
+
+[judge]
+skip-count = 3
+
+[judge.resource-limits]
+cpu = 1
+cpu-tolerance = 0.1
+memory = 512_000_000
+memory-tolerance = 1000
+
+[[judge.languages]]
+name = "Python 3"
+filename = "submission.py"
+run = ["python3", "./submission.py"]
++++
+"#;
+
+    /// Contest directory that is removed once the test finishes
+    struct TempContest(PathBuf);
+
+    impl TempContest {
+        fn new() -> Self {
+            let dir = env::temp_dir().join(format!("judge-load-{}", Uuid::new_v4()));
+            let contest = TempContest(dir);
+            contest.write("contest.md", CONTEST);
+            contest
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        fn task(&self, dir: &str, name: &str) {
+            let page = format!("+++\nname = \"{name}\"\ndifficulty = \"Easy\"\n+++\n");
+            self.write(&format!("{dir}/task.md"), &page);
+        }
+
+        fn test(&self, subtask: &str, test: usize) {
+            self.write(&format!("{subtask}/{test}.in"), &format!("in {test}"));
+            self.write(&format!("{subtask}/{test}.out"), &format!("out {test}"));
+        }
+
+        fn load(&self) -> Result<Contest, LoadError> {
+            Contest::load_dir(&self.0)
+        }
+    }
+
+    impl Drop for TempContest {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn orders_by_number() {
+        let contest = TempContest::new();
+        contest.task("10-c", "C");
+        contest.task("2-b", "B");
+        contest.task("01-a", "A");
+        contest.write(".git/HEAD", "");
+        for subtask in ["01-a/10", "01-a/2", "01-a/1"] {
+            for test in 1..=10 {
+                contest.test(subtask, test);
+            }
+        }
+
+        let loaded = contest.load().unwrap();
+        let names: Vec<_> = loaded.tasks.iter().map(|task| &task.name).collect();
+        assert_eq!(names, ["A", "B", "C"]);
+
+        // subtasks and tests are ordered numerically, not by name
+        let subtasks = &loaded.tasks[0].subtasks;
+        assert_eq!(subtasks.len(), 3);
+        for (subtask, dir) in subtasks.iter().zip(["1", "2", "10"]) {
+            let inputs: Vec<_> = subtask.tests.iter().map(|test| &test.input).collect();
+            let expected: Vec<_> = (1..=10).map(|test| format!("in {test}")).collect();
+            assert_eq!(inputs, expected.iter().collect::<Vec<_>>(), "subtask {dir}");
+        }
+    }
+
+    #[test]
+    fn loads_subtask_settings() {
+        let contest = TempContest::new();
+        contest.task("1-task", "Task");
+        contest.test("1-task/1", 1);
+        contest.test("1-task/2", 1);
+        contest.write("1-task/2/subtask.toml", "score = 30\n");
+
+        let loaded = contest.load().unwrap();
+        let subtasks = &loaded.tasks[0].subtasks;
+        assert_eq!(subtasks[0].score, None);
+        assert_eq!(subtasks[1].score, Some(30));
+        assert_eq!(subtasks[1].tests[0].output, "out 1");
+    }
+
+    #[test]
+    fn answer_tasks_have_no_subtasks() {
+        let contest = TempContest::new();
+        let page = "+++\nname = \"Answer\"\nanswer = \"42\"\ndifficulty = \"Hard\"\n+++\n";
+        contest.write("1-answer/task.md", page);
+        contest.write("1-answer/notes/ignored.txt", "");
+
+        let loaded = contest.load().unwrap();
+        assert_eq!(loaded.tasks[0].answer.as_deref(), Some("42"));
+        assert!(loaded.tasks[0].subtasks.is_empty());
+    }
+
+    #[test]
+    fn rejects_unnumbered_dirs() {
+        let contest = TempContest::new();
+        contest.task("task", "Task");
+        assert!(matches!(contest.load(), Err(LoadError::UnnumberedTask(_))));
+
+        let contest = TempContest::new();
+        contest.task("1-task", "Task");
+        contest.test("1-task/first", 1);
+        assert!(matches!(
+            contest.load(),
+            Err(LoadError::UnnumberedSubtask(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_files() {
+        let contest = TempContest::new();
+        contest.task("1-task", "Task");
+        contest.test("1-task/1", 1);
+        contest.write("1-task/1/2.in", "in 2");
+        assert!(matches!(
+            contest.load(),
+            Err(LoadError::MissingOutput { output, .. }) if output.ends_with("1-task/1/2.out")
+        ));
+
+        let contest = TempContest::new();
+        contest.task("1-task", "Task");
+        contest.write("1-task/1/1.out", "out 1");
+        assert!(matches!(
+            contest.load(),
+            Err(LoadError::MissingInput { input, .. }) if input.ends_with("1-task/1/1.in")
+        ));
+
+        let contest = TempContest::new();
+        contest.task("1-task", "Task");
+        contest.test("1-task/1", 1);
+        contest.test("1-task/1", 3);
+        assert!(matches!(
+            contest.load(),
+            Err(LoadError::MissingTest { test: 2, .. })
+        ));
+    }
+}
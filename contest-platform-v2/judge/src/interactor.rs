@@ -0,0 +1,94 @@
+//! Interactors for interactive tasks.
+//!
+//! The interactor is invoked as `<run command> <input> <expected>`, where each argument is a path
+//! to a file containing the test input and the expected output respectively. Its stdin and stdout
+//! are connected to the contestant's stdout and stdin. As stdout is taken, the first token of
+//! stderr is the partial score, and the rest of stderr is included in the test report as the
+//! interactor message.
+//!
+//! The interactor is trusted, so it runs under the task's limits rather than the contestant's
+//! language-scaled ones, and exceeding them is an error in the task rather than a verdict.
+
+use std::path::Path;
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+
+use crate::{
+    contest::{Config, Language, Program, Test},
+    program::{Outcome, Scratch},
+    sandbox::{self, Interaction, Output, Profile, ResourceLimits},
+};
+
+/// Runs the contestant's program in `dir` against the interactor in `interactor_dir`, each under
+/// its own limits
+#[allow(clippy::too_many_arguments)]
+pub async fn interact(
+    dir: &Path,
+    language: &Language,
+    interactor_dir: &Path,
+    config: &Config,
+    interactor: &Program,
+    resource_limits: ResourceLimits,
+    interactor_limits: ResourceLimits,
+    test: &Test,
+) -> color_eyre::Result<Interaction> {
+    let interactor_language = config
+        .language(&interactor.language)
+        .ok_or_else(|| eyre!("unsupported interactor language: {}", interactor.language))?;
+    let profile = Profile::Run(resource_limits, language.syscall_filter(config)?);
+    let interactor_profile = Profile::Run(
+        interactor_limits,
+        interactor_language.syscall_filter(config)?,
+    );
+
+    let (scratch, files) = Scratch::new(
+        interactor_dir,
+        &[
+            ("input", test.input.as_bytes()),
+            ("expected", test.output.as_bytes()),
+        ],
+    )
+    .await?;
+
+    let interactor_command = [interactor_language.run.clone(), files].concat();
+    let result = sandbox::interact(
+        dir,
+        &language.run,
+        profile,
+        interactor_dir,
+        &interactor_command,
+        interactor_profile,
+    )
+    .await;
+    scratch.remove().await;
+
+    result.wrap_err("failed to execute interactor")
+}
+
+/// Interprets the result reported by the interactor, which ran under `resource_limits`
+pub fn outcome(
+    interactor: &Output,
+    resource_limits: ResourceLimits,
+) -> color_eyre::Result<Outcome> {
+    if let Some(syscall) = interactor.denied_syscall() {
+        bail!("interactor made a restricted syscall: {syscall}");
+    }
+    if let Some(limit) =
+        resource_limits.exceeded(interactor.exit_status(), interactor.resource_usage())
+    {
+        bail!("interactor exceeded its {limit:?} limit");
+    }
+
+    let stderr = interactor.stderr_utf8().unwrap_or_default().trim();
+
+    let message = if interactor.exit_status().code() == Some(7) {
+        stderr.split_once(char::is_whitespace).map(|(_, s)| s)
+    } else {
+        Some(stderr)
+    }
+    .map(str::trim)
+    .filter(|s| !s.is_empty())
+    .map(str::to_owned);
+
+    Outcome::new(interactor.exit_status(), stderr, message).wrap_err("interactor failed")
+}
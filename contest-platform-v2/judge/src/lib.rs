@@ -1,9 +1,25 @@
-use ahash::AHashMap;
-use contest::Contest;
-use once_cell::sync::OnceCell;
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use registry::Contests;
 
+pub mod cache;
+pub mod checker;
+pub mod compare;
 pub mod contest;
+pub mod interactor;
+pub mod program;
+pub mod queue;
+pub mod registry;
+pub mod rejudge;
 pub mod sandbox;
+pub mod scoring;
+pub mod store;
 pub mod submit;
+pub mod worker;
 
-pub static CONTESTS: OnceCell<AHashMap<String, Contest>> = OnceCell::new();
+pub static CONTESTS: Lazy<ArcSwap<Contests>> = Lazy::new(Default::default);
+
+/// Compares two secrets in time that only depends on their length
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
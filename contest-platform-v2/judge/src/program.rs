@@ -0,0 +1,155 @@
+//! Helper programs (checkers and interactors) supplied by the problem setter.
+//!
+//! Results are communicated through the exit code, similarly to testlib:
+//!
+//! - `0`: Accepted
+//! - `1` or `2`: Wrong Answer (`2` is testlib's Presentation Error)
+//! - `7`: Partial Score, with a score between 0 and 1 reported by the program
+//!
+//! Any other exit code is treated as a failure of the program itself.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use tokio::{fs, io};
+use uuid::Uuid;
+
+use crate::{
+    contest::{Config, Contest, Kind, Program},
+    sandbox::{run, Profile},
+    submit::Verdict,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub verdict: Verdict,
+    pub score: Option<f64>,
+    pub message: Option<String>,
+}
+
+impl Outcome {
+    /// Interprets the exit status of a helper program, reading the partial score (if any) from
+    /// the first token of `score`
+    pub fn new(
+        exit_status: ExitStatus,
+        score: &str,
+        message: Option<String>,
+    ) -> color_eyre::Result<Self> {
+        let (verdict, score) = match exit_status.code() {
+            Some(0) => (Verdict::Accepted, None),
+            Some(1 | 2) => (Verdict::WrongAnswer, None),
+            Some(7) => {
+                let score = score
+                    .split_whitespace()
+                    .next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|score| (0.0..=1.0).contains(score))
+                    .ok_or_else(|| eyre!("invalid partial score reported"))?;
+
+                let verdict = if score == 1.0 {
+                    Verdict::Accepted
+                } else if score == 0.0 {
+                    Verdict::WrongAnswer
+                } else {
+                    Verdict::PartialScore
+                };
+
+                (verdict, Some(score))
+            }
+            _ => bail!("{exit_status}: {}", message.unwrap_or_default()),
+        };
+
+        Ok(Outcome {
+            verdict,
+            score,
+            message,
+        })
+    }
+}
+
+/// Compiles the helper programs for every task in the contest into `dir/<task number>`
+#[tracing::instrument(skip(contest))]
+pub async fn compile_all(dir: &Path, contest: &Contest) -> color_eyre::Result<()> {
+    for (idx, task) in contest.tasks.iter().enumerate() {
+        let dir = dir.join((idx + 1).to_string());
+
+        if let Some(checker) = &task.checker {
+            compile(dir.join("checker"), &contest.config, checker)
+                .await
+                .wrap_err_with(|| format!("failed to compile checker for task {}", task.name))?;
+        }
+
+        if let Kind::Interactive { interactor } = &task.kind {
+            compile(dir.join("interactor"), &contest.config, interactor)
+                .await
+                .wrap_err_with(|| format!("failed to compile interactor for task {}", task.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn compile(dir: PathBuf, config: &Config, program: &Program) -> color_eyre::Result<()> {
+    let language = config
+        .language(&program.language)
+        .ok_or_else(|| eyre!("unsupported language: {}", program.language))?;
+
+    if fs::try_exists(&dir).await? {
+        fs::remove_dir_all(&dir).await?;
+    }
+    fs::create_dir_all(&dir).await?;
+    fs::write(dir.join(&language.filename), &program.source).await?;
+
+    if let Some(command) = &language.compile {
+        let profile = Profile::Compile(language.compile_limits(config));
+        let output = run(&dir, command, &[], profile).await?;
+        if !output.exit_status().success() {
+            bail!(
+                "compiler exited with {}: {}",
+                output.exit_status(),
+                output.stderr_utf8().unwrap_or_default()
+            );
+        }
+    }
+
+    tracing::info!("compiled {}", dir.display());
+    Ok(())
+}
+
+/// Scratch directory inside a helper program's directory, used to pass files to the program
+///
+/// Each invocation gets its own scratch directory, as tests are judged concurrently.
+pub struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    /// Writes `files` into a new scratch directory, returning their paths relative to `dir`
+    pub async fn new(dir: &Path, files: &[(&str, &[u8])]) -> io::Result<(Self, Vec<String>)> {
+        let name = Uuid::new_v4().to_string();
+        let scratch = Scratch {
+            path: dir.join(&name),
+        };
+        fs::create_dir(&scratch.path).await?;
+
+        let mut paths = Vec::with_capacity(files.len());
+        for (file, contents) in files {
+            if let Err(e) = fs::write(scratch.path.join(file), contents).await {
+                scratch.remove().await;
+                return Err(e);
+            }
+            paths.push(format!("{name}/{file}"));
+        }
+
+        Ok((scratch, paths))
+    }
+
+    pub async fn remove(self) {
+        if let Err(e) = fs::remove_dir_all(&self.path).await {
+            tracing::error!("failed to clean up {}: {e}", self.path.display());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Queue of submissions waiting to be judged.
+//!
+//! Workers take jobs from the queue one at a time, and the messages they send back about a job are
+//! stored in the submission's [`Entry`]. Local workers run in the server process (see
+//! [`spawn_local_workers`]), while remote ones connect to it over TCP (see [`crate::worker`]).
+//!
+//! Jobs are taken in order of [`Priority`], and jobs of the same priority are taken from each user
+//! in turn, so that one user's flood of submissions doesn't hold up everyone else's (anonymous
+//! submissions all take their turns as one user). Every time the order changes, the submissions
+//! that moved are sent a [`Message::Queued`] with their new position, which is only stored for
+//! their first position (see [`Entry::update`]). A job whose remote worker dies is put back at the
+//! front of the queue.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use ahash::AHashMap;
+use once_cell::sync::Lazy;
+use tokio::sync::{mpsc, oneshot, Notify};
+use uuid::Uuid;
+
+use crate::{
+    registry::Loaded,
+    store::Entry,
+    submit::{self, Job, Message},
+};
+
+/// How urgently a job needs judging, from least to most
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Judging a submission again (e.g. after its task's tests changed)
+    Rejudge,
+    /// A submission made outside of a running contest
+    Practice,
+    /// A submission made during a running contest
+    Contest,
+}
+
+/// A job waiting in the queue, along with where its results go
+#[derive(Debug)]
+pub struct Queued {
+    pub job: Job,
+    pub entry: Arc<Entry>,
+    /// The version of the contest that was current when the submission was made
+    pub contest: Arc<Loaded>,
+    pub priority: Priority,
+    /// User who made the submission (anonymous submissions count as the same user)
+    pub user: Option<String>,
+}
+
+/// Jobs of one priority, which are taken from each user in turn
+#[derive(Debug, Default)]
+struct Class {
+    /// Each user's jobs, in the order they will be taken
+    jobs: AHashMap<Option<String>, VecDeque<Queued>>,
+    /// Users with jobs in the queue, starting with the one whose turn is next
+    turns: VecDeque<Option<String>>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    classes: BTreeMap<Priority, Class>,
+    /// Last position sent to each job in the queue
+    positions: AHashMap<Uuid, u32>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Mutex<Jobs>,
+    available: Notify,
+}
+
+static QUEUE: Lazy<Queue> = Lazy::new(Default::default);
+
+enum Announcement {
+    /// A job's position, which is stored if it's the first one since the job was queued
+    Position(Arc<Entry>, Message, bool),
+    Started(Arc<Entry>, oneshot::Sender<()>),
+}
+
+/// Stores queue positions in the order they were worked out, and the start of judging after all
+/// of the positions worked out before it, so that a stale position never arrives last
+static ANNOUNCER: Lazy<mpsc::UnboundedSender<Announcement>> = Lazy::new(|| {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(announcement) = rx.recv().await {
+            match announcement {
+                Announcement::Position(entry, message, true) => entry.push(&message).await,
+                Announcement::Position(entry, message, false) => entry.update(&message),
+                Announcement::Started(entry, done) => {
+                    if let Err(e) = entry.start().await {
+                        tracing::error!("failed to store submission status: {e}");
+                    }
+                    done.send(()).ok();
+                }
+            }
+        }
+    });
+    tx
+});
+
+impl Queued {
+    /// Marks the job as taken by a worker
+    pub async fn start(&self) {
+        let (tx, rx) = oneshot::channel();
+        ANNOUNCER
+            .send(Announcement::Started(self.entry.clone(), tx))
+            .ok();
+        rx.await.ok();
+    }
+
+    fn tests(&self) -> u32 {
+        let task = &self.contest.tasks[self.job.task - 1];
+        task.subtasks.iter().map(|s| s.tests.len() as u32).sum()
+    }
+}
+
+impl Class {
+    fn push_back(&mut self, queued: Queued) {
+        let user = queued.user.clone();
+        let jobs = self.jobs.entry(user.clone()).or_default();
+        if jobs.is_empty() {
+            self.turns.push_back(user);
+        }
+        jobs.push_back(queued);
+    }
+
+    /// Puts a job first in line, making it the user's turn
+    fn push_front(&mut self, queued: Queued) {
+        let user = queued.user.clone();
+        self.turns.retain(|turn| *turn != user);
+        self.turns.push_front(user.clone());
+        self.jobs.entry(user).or_default().push_front(queued);
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let user = self.turns.pop_front()?;
+        let jobs = self
+            .jobs
+            .get_mut(&user)
+            .expect("user has a turn but no jobs");
+        let queued = jobs.pop_front().expect("user has a turn but no jobs");
+
+        if jobs.is_empty() {
+            self.jobs.remove(&user);
+        } else {
+            self.turns.push_back(user);
+        }
+
+        Some(queued)
+    }
+
+    /// Jobs in the order they will be taken (unless more arrive)
+    fn order(&self) -> impl Iterator<Item = &Queued> {
+        // each round takes the next job of every user with jobs left
+        let rounds = self.jobs.values().map(VecDeque::len).max().unwrap_or(0);
+        (0..rounds).flat_map(move |round| {
+            self.turns
+                .iter()
+                .filter_map(move |user| self.jobs[user].get(round))
+        })
+    }
+}
+
+impl Jobs {
+    fn pop(&mut self) -> Option<Queued> {
+        let queued = self.classes.values_mut().rev().find_map(Class::pop)?;
+        self.positions.remove(&queued.job.id);
+        Some(queued)
+    }
+
+    /// Sends every job whose position changed its new one
+    fn announce(&mut self) {
+        let order = self.classes.values().rev().flat_map(Class::order);
+        for (queued, position) in order.zip(1..) {
+            let previous = self.positions.insert(queued.job.id, position);
+            if previous != Some(position) {
+                let message = Message::Queued {
+                    tests: queued.tests(),
+                    position,
+                };
+                // storing every move would take space quadratic in the length of the queue
+                let first = previous.is_none();
+                let announcement = Announcement::Position(queued.entry.clone(), message, first);
+                ANNOUNCER.send(announcement).ok();
+            }
+        }
+    }
+}
+
+/// Adds a job to the queue, behind every job of the same or a higher priority
+pub fn enqueue(queued: Queued) {
+    let mut jobs = QUEUE.jobs.lock().unwrap();
+    jobs.classes
+        .entry(queued.priority)
+        .or_default()
+        .push_back(queued);
+    jobs.announce();
+    QUEUE.available.notify_one();
+}
+
+/// Puts a job that was taken from the queue back at the front of it
+pub async fn requeue(queued: Queued) {
+    tracing::warn!("requeueing submission {}", queued.job.id);
+    if let Err(e) = queued.entry.requeue().await {
+        tracing::error!("failed to store submission status: {e}");
+    }
+
+    let mut jobs = QUEUE.jobs.lock().unwrap();
+    jobs.classes
+        .entry(queued.priority)
+        .or_default()
+        .push_front(queued);
+    jobs.announce();
+    QUEUE.available.notify_one();
+}
+
+/// Waits for the next job in the queue
+///
+/// This is cancel safe, as the job is only removed from the queue once it is returned.
+pub async fn take() -> Queued {
+    loop {
+        let available = QUEUE.available.notified();
+        {
+            let mut jobs = QUEUE.jobs.lock().unwrap();
+            if let Some(queued) = jobs.pop() {
+                jobs.announce();
+                return queued;
+            }
+        }
+        available.await;
+    }
+}
+
+/// Number of local workers if the `LOCAL_WORKERS` environment variable isn't set, which is one
+/// per CPU core so that runs don't compete for CPU time
+pub fn default_local_workers() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Starts workers in the server process, each judging one job at a time
+pub fn spawn_local_workers(count: usize) {
+    for _ in 0..count {
+        tokio::spawn(async {
+            loop {
+                let queued = take().await;
+                queued.start().await;
+                judge(queued.job, queued.contest, &queued.entry).await;
+            }
+        });
+    }
+}
+
+/// Judges a job in the submission's directory, storing the messages about it in its entry
+pub async fn judge(job: Job, contest: Arc<Loaded>, entry: &Entry) {
+    let (mut messages, _) = submit::spawn(job, contest, entry.dir().to_owned());
+    while let Some(message) = messages.recv().await {
+        entry.push(&message).await;
+    }
+}
@@ -0,0 +1,180 @@
+//! The set of contests served by the judge.
+//!
+//! Contests are loaded from `contests/*.json` or `contests/<name>/contest.md` (see
+//! [`Contest::load_dir`]), and can be reloaded while the server is running. Every load compiles
+//! the helper programs into a fresh directory, so that submissions which are still being judged
+//! keep using the contest definition (and helper programs) they started with. If anything fails
+//! to load, the contests that are currently being served are left untouched.
+
+use std::{
+    ffi::OsStr,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use ahash::AHashMap;
+use color_eyre::eyre::{bail, WrapErr};
+use sha2::{Digest, Sha256};
+use tokio::{fs, runtime::Handle, sync::Mutex, task};
+
+use crate::{contest::Contest, program, CONTESTS};
+
+pub type Contests = AHashMap<String, Arc<Loaded>>;
+
+/// Number of contests loaded so far, used to version the helper program directories
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Prevents concurrent reloads from compiling into each other's directories
+static RELOAD: Mutex<()> = Mutex::const_new(());
+
+/// A contest, together with the helper programs compiled for this version of it
+#[derive(Debug)]
+pub struct Loaded {
+    contest: Contest,
+    programs: PathBuf,
+    version: String,
+}
+
+impl Loaded {
+    /// Compiles the contest's helper programs into a new directory
+    pub async fn new(name: &str, contest: Contest) -> color_eyre::Result<Arc<Self>> {
+        let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+        let version = format!("{:x}", Sha256::digest(serde_json::to_vec(&contest)?));
+        let loaded = Arc::new(Loaded {
+            contest,
+            programs: Path::new("programs")
+                .join(name)
+                .join(generation.to_string()),
+            version,
+        });
+
+        // otherwise a broken seccomp profile would only show up once submissions fail
+        for language in &loaded.config.languages {
+            language
+                .syscall_filter(&loaded.config)
+                .wrap_err_with(|| format!("invalid seccomp profile for {}", language.name))?;
+        }
+
+        // the programs are cleaned up when `loaded` is dropped if compilation fails
+        program::compile_all(&loaded.programs, &loaded)
+            .await
+            .wrap_err_with(|| format!("failed to compile helper programs for {name}"))?;
+
+        Ok(loaded)
+    }
+
+    /// Hash of the contest definition, which is the same wherever the same contest is loaded
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Directory containing the compiled helper programs for a task (numbered from 1)
+    pub fn programs(&self, task: usize) -> PathBuf {
+        self.programs.join(task.to_string())
+    }
+}
+
+impl Deref for Loaded {
+    type Target = Contest;
+
+    fn deref(&self) -> &Contest {
+        &self.contest
+    }
+}
+
+impl Drop for Loaded {
+    // runs once the contest has been replaced and no submission is using it anymore
+    fn drop(&mut self) {
+        // resolved now, in case the working directory changes before it's removed
+        let programs =
+            std::path::absolute(&self.programs).unwrap_or_else(|_| self.programs.clone());
+        let remove = move || match std::fs::remove_dir_all(&programs) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                tracing::error!("failed to clean up {}: {e}", programs.display())
+            }
+            _ => {}
+        };
+
+        // the last submission using the contest may drop it from within an async task
+        match Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// Loads and compiles every contest in `dir`, failing if any of them can't be loaded
+#[tracing::instrument]
+pub async fn load(dir: &Path) -> color_eyre::Result<Contests> {
+    let mut contests = AHashMap::new();
+
+    let mut read_dir = fs::read_dir(dir)
+        .await
+        .wrap_err("failed to scan contests directory")?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        let Some(contest) = read(&path).await? else {
+            continue;
+        };
+
+        let name = path
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .expect("non UTF-8 filename")
+            .to_owned();
+        if contests.contains_key(&name) {
+            bail!("contest {name} is defined more than once");
+        }
+
+        let loaded = Loaded::new(&name, contest).await?;
+        tracing::info!("loaded contest {} ({})", loaded.name, path.display());
+        contests.insert(name, loaded);
+    }
+
+    Ok(contests)
+}
+
+/// Reads a contest from either a directory (see [`Contest::load_dir`]) or a JSON file, returning
+/// `None` if `path` is neither
+pub async fn read(path: &Path) -> color_eyre::Result<Option<Contest>> {
+    let contest = if path.join("contest.md").is_file() {
+        let dir = path.to_owned();
+        task::spawn_blocking(move || Contest::load_dir(dir))
+            .await?
+            .wrap_err_with(|| format!("failed to load {}", path.display()))?
+    } else if path.extension().and_then(OsStr::to_str) == Some("json") {
+        let input = fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        Contest::load(&input).wrap_err_with(|| format!("failed to load {}", path.display()))?
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(contest))
+}
+
+/// Contests that are currently being served
+pub fn contests() -> Arc<Contests> {
+    CONTESTS.load_full()
+}
+
+/// Loads the contests in `dir` and replaces the ones currently being served, returning the names
+/// of the contests that were loaded
+pub async fn reload(dir: &Path) -> color_eyre::Result<Vec<String>> {
+    let _guard = RELOAD.lock().await;
+
+    let contests = load(dir).await?;
+    let mut names: Vec<_> = contests.keys().cloned().collect();
+    names.sort_unstable();
+
+    CONTESTS.store(Arc::new(contests));
+    tracing::info!("reloaded {} contest(s)", names.len());
+
+    Ok(names)
+}
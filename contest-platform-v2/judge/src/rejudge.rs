@@ -0,0 +1,303 @@
+//! Judging stored submissions again against the current contest definitions (e.g. after a broken
+//! test is fixed).
+//!
+//! Rejudged submissions keep their IDs, and wait in the queue behind every new submission (see
+//! [`Priority::Rejudge`]). Their stored results and event logs are only replaced once they have
+//! been judged again (see [`Entry::reopen`]), after which a [`Diff`] between the old and new
+//! results is sent back.
+
+use std::sync::Arc;
+
+use color_eyre::eyre::{bail, ensure, eyre, WrapErr};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{fs, io, sync::mpsc};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::{
+    queue::{self, Priority, Queued},
+    registry,
+    store::{self, Entry, Events, Record, Status},
+    submit::{submit_answer, Job, Message, Verdict, ANSWER_FILENAME},
+};
+
+/// Submissions to judge again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "scope", rename_all = "kebab-case")]
+pub enum Selection {
+    /// A single submission
+    Submission { id: Uuid },
+    /// Every submission to a task
+    Task {
+        contest: String,
+        /// Task number (starting from 1)
+        task: usize,
+    },
+    /// Every submission to any task of a contest
+    Contest { contest: String },
+}
+
+/// Difference between a submission's results before and after judging it again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Diff {
+    pub id: Uuid,
+    pub contest: String,
+    /// Task number (starting from 1)
+    pub task: usize,
+    pub user: Option<String>,
+    pub old: Outcome,
+    pub new: Outcome,
+    /// Tests whose verdict changed
+    pub tests: Vec<TestChange>,
+}
+
+/// Overall result of judging a submission
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Outcome {
+    pub status: Status,
+    /// Verdict for the task (if judging finished without an error)
+    pub verdict: Option<Verdict>,
+    /// Points for the task (if judging finished without an error)
+    pub score: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TestChange {
+    /// Subtask number (starting from 1)
+    pub subtask: usize,
+    /// Test number within the subtask (starting from 1)
+    pub test: usize,
+    /// `None` if the test didn't exist or wasn't judged
+    pub old: Option<Verdict>,
+    /// `None` if the test doesn't exist anymore or wasn't judged
+    pub new: Option<Verdict>,
+}
+
+#[derive(Debug, Error)]
+pub enum RejudgeError {
+    #[error("contest {0} not found")]
+    ContestNotFound(String),
+    #[error("task #{1} for contest {0} not found")]
+    TaskNotFound(String, usize),
+    #[error("submission {0} not found")]
+    SubmissionNotFound(Uuid),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl Outcome {
+    fn new(record: &Record) -> Self {
+        Outcome {
+            status: record.status,
+            verdict: record.report.as_ref().map(|report| report.task),
+            score: record.score.as_ref().map(|score| score.task),
+        }
+    }
+}
+
+impl Diff {
+    fn new(old: &Record, new: &Record) -> Self {
+        let verdicts = |record: &Record| -> Vec<Vec<Verdict>> {
+            let Some(report) = &record.report else {
+                return Vec::new();
+            };
+            report
+                .tests
+                .iter()
+                .map(|tests| tests.iter().map(|test| test.verdict).collect())
+                .collect()
+        };
+        let (old_verdicts, new_verdicts) = (verdicts(old), verdicts(new));
+
+        let mut tests = Vec::new();
+        for subtask in 0..old_verdicts.len().max(new_verdicts.len()) {
+            let old_tests = old_verdicts.get(subtask).map_or(&[][..], Vec::as_slice);
+            let new_tests = new_verdicts.get(subtask).map_or(&[][..], Vec::as_slice);
+
+            for test in 0..old_tests.len().max(new_tests.len()) {
+                let (old, new) = (old_tests.get(test).copied(), new_tests.get(test).copied());
+                if old != new {
+                    tests.push(TestChange {
+                        subtask: subtask + 1,
+                        test: test + 1,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+
+        Diff {
+            id: new.id,
+            contest: new.contest.clone(),
+            task: new.task,
+            user: new.user.clone(),
+            old: Outcome::new(old),
+            new: Outcome::new(new),
+            tests,
+        }
+    }
+}
+
+/// Judges the selected submissions again, sending the diff of each one once it has been judged
+///
+/// Submissions that are still being judged are left alone.
+pub async fn rejudge(selection: Selection) -> Result<mpsc::Receiver<Diff>, RejudgeError> {
+    let records = select(&selection).await?;
+    tracing::info!("rejudging {} submission(s)", records.len());
+
+    let (tx, rx) = mpsc::channel(16);
+    for record in records {
+        let entry = match Entry::reopen(record.clone()).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                tracing::info!("submission {} is still being judged", record.id);
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("failed to reset submission {}: {e}", record.id);
+                continue;
+            }
+        };
+
+        let events = entry.subscribe(0);
+        if let Err(report) = resubmit(&record, &entry).await {
+            let reason = format!("{report:#}");
+            entry.push(&Message::Error { reason }).await;
+        }
+
+        tokio::spawn(send_diff(record, events, tx.clone()));
+    }
+
+    Ok(rx)
+}
+
+/// Finds the stored records of the selected submissions
+async fn select(selection: &Selection) -> Result<Vec<Record>, RejudgeError> {
+    let (contest, task) = match selection {
+        Selection::Submission { id } => {
+            let record = store::record(*id)
+                .await?
+                .ok_or(RejudgeError::SubmissionNotFound(*id))?;
+            return Ok(vec![record]);
+        }
+        Selection::Task { contest, task } => (contest, Some(*task)),
+        Selection::Contest { contest } => (contest, None),
+    };
+
+    let loaded = registry::contests()
+        .get(contest)
+        .cloned()
+        .ok_or_else(|| RejudgeError::ContestNotFound(contest.clone()))?;
+    if let Some(task) = task {
+        if !(1..=loaded.tasks.len()).contains(&task) {
+            return Err(RejudgeError::TaskNotFound(contest.clone(), task));
+        }
+    }
+
+    let mut records = store::records().await?;
+    records
+        .retain(|record| record.contest == *contest && task.is_none_or(|task| record.task == task));
+    records.sort_by_key(|record| record.timings.submitted);
+
+    Ok(records)
+}
+
+/// Judges a reopened submission with the current version of its contest
+async fn resubmit(record: &Record, entry: &Arc<Entry>) -> color_eyre::Result<()> {
+    let contest = registry::contests()
+        .get(&record.contest)
+        .cloned()
+        .ok_or_else(|| eyre!("contest {} not found", record.contest))?;
+    ensure!(
+        (1..=contest.tasks.len()).contains(&record.task),
+        "task #{} for contest {} not found",
+        record.task,
+        record.contest
+    );
+    let task = &contest.tasks[record.task - 1];
+
+    match (&record.language, &task.answer) {
+        (Some(language), None) => {
+            let filename = &contest
+                .config
+                .languages
+                .iter()
+                .find(|lang| lang.name == *language)
+                .ok_or_else(|| eyre!("unsupported language: {language}"))?
+                .filename;
+            let code = fs::read_to_string(entry.dir().join(filename))
+                .await
+                .wrap_err("failed to read code")?;
+
+            let job = Job {
+                id: record.id,
+                contest: record.contest.clone(),
+                task: record.task,
+                language: language.clone(),
+                code,
+                version: contest.version().to_owned(),
+            };
+            queue::enqueue(Queued {
+                job,
+                entry: entry.clone(),
+                contest,
+                priority: Priority::Rejudge,
+                user: record.user.clone(),
+            });
+        }
+        (None, Some(_)) => {
+            let answer = fs::read_to_string(entry.dir().join(ANSWER_FILENAME))
+                .await
+                .wrap_err("failed to read answer")?;
+            tokio::spawn(submit_answer(entry.clone(), contest, record.task, answer));
+        }
+        (Some(_), Some(_)) => bail!(
+            "task #{} for contest {} now takes an answer, not code",
+            record.task,
+            record.contest
+        ),
+        (None, None) => bail!(
+            "task #{} for contest {} now takes code, not an answer",
+            record.task,
+            record.contest
+        ),
+    }
+
+    Ok(())
+}
+
+/// Waits for a submission to be judged again, then sends the diff from its old record
+async fn send_diff(old: Record, mut events: Events, diffs: mpsc::Sender<Diff>) {
+    // the event log is only closed once the final message has been stored
+    let mut last = None;
+    while let Some((_, json)) = events.next().await {
+        last = Some(json);
+    }
+
+    // the old record is kept if judging failed, so the error only shows up in the final message
+    if let Some(Ok(Message::Error { reason })) = last.map(|json| serde_json::from_str(&json)) {
+        let new = Record {
+            status: Status::Error,
+            report: None,
+            score: None,
+            compile_stderr: None,
+            error: Some(reason),
+            ..old.clone()
+        };
+        diffs.send(Diff::new(&old, &new)).await.ok();
+        return;
+    }
+
+    match store::record(old.id).await {
+        Ok(Some(new)) => {
+            diffs.send(Diff::new(&old, &new)).await.ok();
+        }
+        Ok(None) => tracing::error!("record of submission {} disappeared", old.id),
+        Err(e) => tracing::error!("failed to read record of submission {}: {e}", old.id),
+    }
+}
@@ -1,22 +1,58 @@
 use std::{
+    env,
+    future::Future,
     path::Path,
     process::{ExitStatus, Stdio},
     str,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-pub use resource::{ResourceLimits, ResourceUsage};
+use cgroup::Cgroup;
+pub use interact::{interact, Exchange, Interaction, Party};
+use namespace::Jail;
+use once_cell::sync::Lazy;
+pub use resource::{CompileLimit, CompileLimits, Limit, ResourceLimits, ResourceUsage};
+use seccomp::Supervisor;
+pub use seccomp::{
+    ArgCondition, ArgOp, ArgWidth, OnDenied, SeccompError, SeccompProfile, SyscallFilter,
+    SyscallRule, DEFAULT_PROFILE,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, Error, ErrorKind},
-    process::Command,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, Error, ErrorKind},
+    process::{Child, ChildStdin, Command},
 };
 
+mod cgroup;
+mod interact;
+mod namespace;
 mod resource;
 mod seccomp;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Maximum number of processes (and threads) in a run, which stops fork bombs (cgroups only)
+const MAX_PROCESSES: u64 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Profile {
-    Compile,
-    Run(ResourceLimits),
+    Compile(CompileLimits),
+    Run(ResourceLimits, Arc<SyscallFilter>),
+}
+
+impl Profile {
+    pub fn wall_time_limit(&self) -> Duration {
+        match self {
+            Profile::Compile(compile_limits) => compile_limits.wall_time_limit(),
+            Profile::Run(resource_limits, _) => resource_limits.wall_time_limit(),
+        }
+    }
+
+    /// Maximum number of bytes the process may write to each of stdout and stderr
+    pub fn output_limit(&self) -> u64 {
+        match self {
+            Profile::Compile(compile_limits) => compile_limits.output,
+            Profile::Run(resource_limits, _) => resource_limits.output,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,6 +61,7 @@ pub struct Output {
     stdout: Vec<u8>,
     stderr: Vec<u8>,
     resource_usage: ResourceUsage,
+    denied_syscall: Option<String>,
 }
 
 impl Output {
@@ -33,12 +70,14 @@ impl Output {
         stdout: Vec<u8>,
         stderr: Vec<u8>,
         resource_usage: ResourceUsage,
+        denied_syscall: Option<String>,
     ) -> Self {
         Self {
             exit_status,
             stdout,
             stderr,
             resource_usage,
+            denied_syscall,
         }
     }
 
@@ -65,6 +104,20 @@ impl Output {
     pub fn resource_usage(&self) -> ResourceUsage {
         self.resource_usage
     }
+
+    /// Syscall that the process was killed for making, if its seccomp profile kills on denied
+    /// syscalls
+    pub fn denied_syscall(&self) -> Option<&str> {
+        self.denied_syscall.as_deref()
+    }
+}
+
+/// A process spawned in the sandbox, along with the cgroup it runs in (if cgroups are available)
+/// and the supervisor of its seccomp filter (if it needs one)
+struct Spawned {
+    child: Child,
+    cgroup: Option<Cgroup>,
+    supervisor: Option<Supervisor>,
 }
 
 pub async fn run(
@@ -73,63 +126,266 @@ pub async fn run(
     stdin: &[u8],
     profile: Profile,
 ) -> Result<Output, Error> {
-    let dir = dir.as_ref();
-
-    let mut child = {
-        let (executable, args) = command
-            .split_first()
-            .ok_or_else(|| Error::new(ErrorKind::NotFound, "empty command"))?;
-
-        let mut cmd = Command::new(executable);
-        cmd.args(args)
-            .current_dir(dir)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        if let Profile::Run(resource_limits) = profile {
-            unsafe {
-                cmd.pre_exec(move || {
-                    resource_limits.set()?;
-
-                    seccomp::apply_filters().map_err(|e| {
-                        Error::new(ErrorKind::Other, format!("seccomp failed: {e}"))
-                    })?;
-
-                    Ok(())
-                });
+    let Spawned {
+        mut child,
+        cgroup,
+        supervisor,
+    } = spawn(dir.as_ref(), command, &profile)?;
+    let start = Instant::now();
+    let pid = child.id().expect("child process has no PID") as i32;
+
+    let execution = async {
+        // all three pipes are serviced at once, as the process may block on any one of them
+        // (e.g. echoing its input before reading all of it)
+        let limit = profile.output_limit();
+        let (stdin_pipe, stdout_pipe, stderr_pipe) = (
+            child.stdin.take().expect("no stdin"),
+            child.stdout.take().expect("no stdout"),
+            child.stderr.take().expect("no stderr"),
+        );
+        let (_, (stdout, stdout_exceeded), (stderr, stderr_exceeded)) = tokio::join!(
+            write_stdin(stdin_pipe, stdin),
+            read_limited(stdout_pipe, limit, pid, "stdout"),
+            read_limited(stderr_pipe, limit, pid, "stderr"),
+        );
+
+        let (exit_status, mut resource_usage) = reap(pid, cgroup.as_ref()).await?;
+        resource_usage.output_limit_exceeded = stdout_exceeded || stderr_exceeded;
+        Ok((exit_status, stdout, stderr, resource_usage))
+    };
+
+    let (exit_status, stdout, stderr, mut resource_usage) =
+        with_deadline(&profile, &[pid], execution).await?;
+    resource_usage.wall_time = start.elapsed();
+    let denied_syscall = supervisor.and_then(|supervisor| supervisor.denied());
+
+    Ok(Output::new(
+        exit_status,
+        stdout,
+        stderr,
+        resource_usage,
+        denied_syscall,
+    ))
+}
+
+/// Checks which kinds of isolation are available on the blocking pool, as checking for namespaces
+/// runs a process
+///
+/// Otherwise the checks happen (blocking the runtime) on the first run, so this should be called
+/// at startup.
+pub async fn probe() {
+    tokio::task::spawn_blocking(|| {
+        Lazy::force(&cgroup::PARENT);
+        Lazy::force(&namespace::AVAILABLE);
+    })
+    .await
+    .expect("probing the sandbox panicked");
+}
+
+/// Spawns a command in the sandbox
+fn spawn(dir: &Path, command: &[String], profile: &Profile) -> Result<Spawned, Error> {
+    let (executable, args) = command
+        .split_first()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "empty command"))?;
+
+    let memory = match profile {
+        Profile::Compile(compile_limits) => compile_limits.memory,
+        Profile::Run(resource_limits, _) => resource_limits.memory_cap(),
+    };
+    let cgroup = Cgroup::new(memory, MAX_PROCESSES)?;
+    let procs = cgroup.as_ref().map(Cgroup::procs);
+
+    // compilers write temporary files all over the place, so only runs are isolated
+    let jail = match profile {
+        Profile::Compile(_) => None,
+        Profile::Run(..) => Jail::new(dir)?,
+    };
+    let mut supervisor = Supervisor::start(profile)?;
+    let supervisor_fd = supervisor.as_ref().map(Supervisor::run_fd);
+
+    let mut cmd = Command::new(executable);
+    cmd.args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+
+    if jail.is_some() {
+        // the judge's environment (e.g. `ADMIN_TOKEN`) is none of the run's business
+        cmd.env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_default());
+    }
+
+    let pre_exec_profile = profile.clone();
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(procs) = procs {
+                cgroup::enter(procs)?;
+            }
+
+            if let Some(jail) = &jail {
+                jail.enter()?;
             }
+
+            // the cgroup limits memory across all of the run's processes
+            let limit_memory = procs.is_none();
+            match &pre_exec_profile {
+                Profile::Compile(compile_limits) => compile_limits.set(limit_memory)?,
+                Profile::Run(resource_limits, _) => resource_limits.set(limit_memory)?,
+            }
+
+            seccomp::apply_filters(&pre_exec_profile, supervisor_fd)
+                .map_err(|e| Error::other(format!("seccomp failed: {e}")))?;
+
+            Ok(())
+        });
+    }
+
+    let child = cmd.spawn();
+    if let Some(supervisor) = &mut supervisor {
+        supervisor.spawned();
+    }
+
+    Ok(Spawned {
+        child: child?,
+        cgroup,
+        supervisor,
+    })
+}
+
+/// Writes all of `input` to the process's stdin, then closes it
+async fn write_stdin(mut stdin: ChildStdin, input: &[u8]) {
+    if let Err(e) = stdin.write_all(input).await {
+        // the process doesn't have to read all of its input
+        if e.kind() != ErrorKind::BrokenPipe {
+            tracing::error!("failed to write stdin: {e}");
         }
+    }
+}
 
-        cmd.spawn()?
-    };
+/// Reads `pipe` to the end, unless more than `limit` bytes come through it first, in which case
+/// the process group of `pid` is killed and the output is cut off at the limit
+async fn read_limited(
+    pipe: impl AsyncRead + Unpin,
+    limit: u64,
+    pid: i32,
+    name: &str,
+) -> (Vec<u8>, bool) {
+    let mut buf = Vec::new();
+    if let Err(e) = pipe.take(limit + 1).read_to_end(&mut buf).await {
+        tracing::error!("failed to read {name}: {e}");
+    }
 
-    if let Err(e) = child.stdin.take().expect("no stdin").write_all(stdin).await {
-        tracing::error!("failed to write stdin: {e}");
+    let exceeded = buf.len() as u64 > limit;
+    if exceeded {
+        tracing::warn!("output limit exceeded on {name}, killing process group {pid}");
+        buf.truncate(limit as usize);
+        unsafe { libc::killpg(pid, libc::SIGKILL) };
     }
 
-    let (stdout, stderr) = {
-        let (mut stdout, mut stderr) = (
-            child.stdout.take().expect("no stdout"),
-            child.stderr.take().expect("no stderr"),
-        );
-        let (mut stdout_buf, mut stderr_buf) = (Vec::new(), Vec::new());
+    (buf, exceeded)
+}
+
+async fn reap(pid: i32, cgroup: Option<&Cgroup>) -> Result<(ExitStatus, ResourceUsage), Error> {
+    let (exit_status, mut resource_usage) =
+        tokio::task::spawn_blocking(move || resource::wait4(pid)).await??;
+
+    if let Some(cgroup) = cgroup {
+        cgroup.measure(&mut resource_usage)?;
+    }
+
+    Ok((exit_status, resource_usage))
+}
 
-        if let Err(e) = stdout.read_to_end(&mut stdout_buf).await {
-            tracing::error!("failed to read stdout: {e}");
+/// Drives `execution` to completion, killing the process groups of `pids` if the profile's wall
+/// time limit expires first
+async fn with_deadline<T>(
+    profile: &Profile,
+    pids: &[i32],
+    execution: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let mut cancelled = Cancelled(pids);
+    tokio::pin!(execution);
+    let result = tokio::select! {
+        result = &mut execution => result,
+        _ = tokio::time::sleep(profile.wall_time_limit()) => {
+            for &pid in pids {
+                tracing::warn!("wall time limit exceeded, killing process group {pid}");
+                // each child is the leader of its own process group, so this also takes out
+                // anything it forked
+                unsafe { libc::killpg(pid, libc::SIGKILL) };
+            }
+            execution.await
         }
+    };
 
-        if let Err(e) = stderr.read_to_end(&mut stderr_buf).await {
-            tracing::error!("failed to read stderr: {e}");
+    // the processes have been reaped, so their IDs may already belong to someone else
+    cancelled.0 = &[];
+    result
+}
+
+/// Kills the process groups of a run whose future was dropped before they were reaped (e.g. as
+/// the judging was cancelled), which would otherwise carry on without cgroups to stop them
+struct Cancelled<'a>(&'a [i32]);
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        for &pid in self.0 {
+            tracing::warn!("run cancelled, killing process group {pid}");
+            unsafe { libc::killpg(pid, libc::SIGKILL) };
         }
+    }
+}
 
-        (stdout_buf, stderr_buf)
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let (exit_status, resource_usage) = tokio::task::spawn_blocking(move || {
-        resource::wait4(child.id().expect("child process has no PID") as i32)
-    })
-    .await??;
+    /// Several times the size of a pipe buffer (64 KiB on Linux)
+    const LARGE: usize = 1024 * 1024;
+
+    async fn run_sh(script: &str, stdin: &[u8]) -> Output {
+        let command = ["sh", "-c", script].map(String::from);
+        let profile = Profile::Compile(CompileLimits::default());
+        run(env::temp_dir(), &command, stdin, profile)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn echoes_large_input() {
+        let input = b"0123456789abcdef\n".repeat(LARGE / 16);
+        let output = run_sh("cat", &input).await;
 
-    Ok(Output::new(exit_status, stdout, stderr, resource_usage))
+        assert!(output.exit_status().success());
+        assert_eq!(output.stdout(), input);
+    }
+
+    #[tokio::test]
+    async fn large_stderr_before_stdout() {
+        let output = run_sh(&format!("head -c {LARGE} /dev/zero >&2; echo done"), &[]).await;
+
+        assert!(output.exit_status().success());
+        assert_eq!(output.stderr().len(), LARGE);
+        assert_eq!(output.stdout(), b"done\n");
+    }
+
+    #[tokio::test]
+    async fn large_stderr_before_reading_input() {
+        let input = vec![b'x'; LARGE];
+        let output = run_sh(&format!("head -c {LARGE} /dev/zero >&2; cat"), &input).await;
+
+        assert!(output.exit_status().success());
+        assert_eq!(output.stderr().len(), LARGE);
+        assert_eq!(output.stdout(), input);
+    }
+
+    #[tokio::test]
+    async fn unread_input() {
+        let output = run_sh("echo done", &vec![b'x'; LARGE]).await;
+
+        assert!(output.exit_status().success());
+        assert_eq!(output.stdout(), b"done\n");
+    }
 }
@@ -0,0 +1,229 @@
+//! Per-run cgroups (v2), which limit memory across every process of a run (including memory that
+//! `RLIMIT_DATA` doesn't cover, such as shared mappings), stop fork bombs and measure peak memory
+//! and CPU time precisely, even for processes that were never reaped.
+//!
+//! The judge needs a delegated cgroup with the `cpu`, `memory` and `pids` controllers available.
+//! Since a cgroup can only hand controllers to its children while it has no processes of its own,
+//! the judge moves itself into a `judge` child cgroup, and each run gets a sibling cgroup. If any of
+//! this fails (or `JUDGE_CGROUPS=off` is set), the sandbox falls back to rlimits.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    mem,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+use super::ResourceUsage;
+
+const MOUNT: &str = "/sys/fs/cgroup";
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// Parent of the per-run cgroups, or `None` if cgroups are unavailable
+pub(super) static PARENT: Lazy<Option<PathBuf>> = Lazy::new(|| match delegate() {
+    Ok(parent) => {
+        tracing::info!("using cgroups in {}", parent.display());
+        Some(parent)
+    }
+    Err(e) => {
+        tracing::warn!("cgroups unavailable, falling back to rlimits: {e}");
+        None
+    }
+});
+
+fn delegate() -> io::Result<PathBuf> {
+    if env::var("JUDGE_CGROUPS").is_ok_and(|value| value == "off") {
+        return Err(io::Error::other("disabled by JUDGE_CGROUPS"));
+    }
+
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::other("not in a cgroup v2 hierarchy"))?;
+    let parent = Path::new(MOUNT).join(own.trim_start_matches('/'));
+
+    let controllers = parent.join("cgroup.controllers");
+    let available = fs::read_to_string(&controllers).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to read {}: {e}", controllers.display()),
+        )
+    })?;
+    for controller in CONTROLLERS {
+        if !available.split_whitespace().any(|c| c == controller) {
+            return Err(io::Error::other(format!(
+                "{controller} controller is not available in {}",
+                parent.display()
+            )));
+        }
+    }
+
+    let judge = parent.join("judge");
+    if !judge.exists() {
+        fs::create_dir(&judge)?;
+    }
+    fs::write(judge.join("cgroup.procs"), process::id().to_string())?;
+
+    let enable = CONTROLLERS.map(|controller| format!("+{controller}"));
+    fs::write(parent.join("cgroup.subtree_control"), enable.join(" "))?;
+
+    Ok(parent)
+}
+
+/// A cgroup for a single run, which kills anything left in it and is removed when dropped
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    /// Creates a cgroup limited to `memory` bytes, `pids` processes (or threads) and one CPU,
+    /// returning `None` if cgroups are unavailable
+    pub fn new(memory: u64, pids: u64) -> io::Result<Option<Self>> {
+        let Some(parent) = &*PARENT else {
+            return Ok(None);
+        };
+
+        let path = parent.join(format!("run-{}", Uuid::new_v4()));
+        fs::create_dir(&path)?;
+
+        let procs = match OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))
+        {
+            Ok(procs) => procs,
+            Err(e) => {
+                fs::remove_dir(&path)?;
+                return Err(e);
+            }
+        };
+        // dropped (and removed) if configuring it fails
+        let cgroup = Cgroup { path, procs };
+
+        cgroup.write("memory.max", &memory.to_string())?;
+        cgroup.write("pids.max", &pids.to_string())?;
+        cgroup.write("cpu.max", "100000 100000")?;
+        // swap accounting may be disabled, in which case there is no swap to limit
+        match cgroup.write("memory.swap.max", "0") {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        Ok(Some(cgroup))
+    }
+
+    /// File descriptor of the cgroup's `cgroup.procs`, for [`enter`]
+    pub fn procs(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// Replaces the usage measured by `wait4` with the cgroup's statistics, which include every
+    /// process that ran in it
+    pub fn measure(&self, resource_usage: &mut ResourceUsage) -> io::Result<()> {
+        let cpu = self.read("cpu.stat")?;
+        let stat = |key: &str| {
+            cpu.lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.parse().ok())
+                .map(Duration::from_micros)
+        };
+
+        if let (Some(user_time), Some(sys_time)) = (stat("user_usec"), stat("system_usec")) {
+            resource_usage.user_time = user_time;
+            resource_usage.sys_time = sys_time;
+        }
+
+        // `memory.peak` was added in Linux 5.19
+        match self.read("memory.peak") {
+            Ok(peak) => {
+                resource_usage.memory = peak
+                    .trim()
+                    .parse()
+                    .map_err(|_| io::Error::other("invalid memory.peak"))?
+            }
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        resource_usage.oom_killed = self
+            .read("memory.events")?
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill ")?.parse::<u64>().ok())
+            .is_some_and(|count| count > 0);
+
+        Ok(())
+    }
+
+    fn read(&self, file: &str) -> io::Result<String> {
+        fs::read_to_string(self.path.join(file))
+    }
+
+    fn write(&self, file: &str, contents: &str) -> io::Result<()> {
+        // files can't be created in a cgroup, so `fs::write` would report missing ones as
+        // permission errors
+        OpenOptions::new()
+            .write(true)
+            .open(self.path.join(file))?
+            .write_all(contents.as_bytes())
+    }
+}
+
+/// Moves the calling process into the cgroup whose `cgroup.procs` is open as `procs`
+///
+/// Only makes a single `write` syscall, so it can be called between `fork` and `exec`.
+pub fn enter(procs: RawFd) -> io::Result<()> {
+    let result = unsafe { libc::write(procs, b"0".as_ptr().cast(), 1) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // `cgroup.kill` was added in Linux 5.14, before which the processes are killed one by one
+        if self.write("cgroup.kill", "1").is_err() {
+            for pid in self.read("cgroup.procs").unwrap_or_default().lines() {
+                if let Ok(pid) = pid.parse() {
+                    unsafe { libc::kill(pid, libc::SIGKILL) };
+                }
+            }
+        }
+
+        // the killed processes have to exit before the cgroup can be removed, which is waited
+        // for on the blocking pool rather than in the async task that dropped it
+        let path = mem::take(&mut self.path);
+        match Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(move || remove(&path))),
+            Err(_) => remove(&path),
+        }
+    }
+}
+
+/// Removes an emptied cgroup, retrying while its processes are still exiting
+fn remove(path: &Path) {
+    for _ in 0..100 {
+        match fs::remove_dir(path) {
+            Err(e) if e.kind() == ErrorKind::ResourceBusy => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(e) => {
+                tracing::error!("failed to remove cgroup {}: {e}", path.display());
+                return;
+            }
+            Ok(()) => return,
+        }
+    }
+
+    tracing::error!("failed to remove cgroup {}: still in use", path.display());
+}
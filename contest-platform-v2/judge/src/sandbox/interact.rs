@@ -0,0 +1,192 @@
+use std::{path::Path, sync::Mutex, time::Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error};
+
+use super::{read_limited, reap, spawn, with_deadline, Output, Profile, Spawned};
+
+/// Maximum number of bytes of the interaction that are kept in the transcript
+const TRANSCRIPT_LIMIT: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interaction {
+    pub contestant: Output,
+    pub interactor: Output,
+    pub transcript: Vec<Exchange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Exchange {
+    pub from: Party,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Party {
+    Contestant,
+    Interactor,
+}
+
+#[derive(Default)]
+struct Transcript {
+    exchanges: Vec<Exchange>,
+    len: usize,
+}
+
+impl Transcript {
+    fn record(&mut self, from: Party, data: &[u8]) {
+        let remaining = TRANSCRIPT_LIMIT.saturating_sub(self.len);
+        if remaining == 0 {
+            return;
+        }
+
+        let data = &data[..data.len().min(remaining)];
+        self.len += data.len();
+        let data = String::from_utf8_lossy(data);
+
+        match self.exchanges.last_mut() {
+            Some(last) if last.from == from => last.data.push_str(&data),
+            _ => self.exchanges.push(Exchange {
+                from,
+                data: data.into_owned(),
+            }),
+        }
+    }
+}
+
+/// Runs the contestant's program and the interactor with each one's stdout connected to the
+/// other's stdin, recording everything that passes between them
+///
+/// Both are stopped once the contestant's wall time limit expires.
+pub async fn interact(
+    dir: impl AsRef<Path>,
+    command: &[String],
+    profile: Profile,
+    interactor_dir: impl AsRef<Path>,
+    interactor_command: &[String],
+    interactor_profile: Profile,
+) -> Result<Interaction, Error> {
+    let Spawned {
+        child: mut contestant,
+        cgroup: contestant_cgroup,
+        supervisor: contestant_supervisor,
+    } = spawn(dir.as_ref(), command, &profile)?;
+    let contestant_pid = contestant.id().expect("child process has no PID") as i32;
+
+    let Spawned {
+        child: mut interactor,
+        cgroup: interactor_cgroup,
+        supervisor: interactor_supervisor,
+    } = match spawn(
+        interactor_dir.as_ref(),
+        interactor_command,
+        &interactor_profile,
+    ) {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            unsafe { libc::killpg(contestant_pid, libc::SIGKILL) };
+            reap(contestant_pid, None).await?;
+            return Err(e);
+        }
+    };
+    let interactor_pid = interactor.id().expect("child process has no PID") as i32;
+
+    let start = Instant::now();
+    let transcript = Mutex::new(Transcript::default());
+
+    let execution = async {
+        let (contestant_stderr, interactor_stderr, ..) = tokio::join!(
+            read_limited(
+                contestant.stderr.take().expect("no stderr"),
+                profile.output_limit(),
+                contestant_pid,
+                "contestant stderr",
+            ),
+            read_limited(
+                interactor.stderr.take().expect("no stderr"),
+                interactor_profile.output_limit(),
+                interactor_pid,
+                "interactor stderr",
+            ),
+            forward(
+                contestant.stdout.take().expect("no stdout"),
+                interactor.stdin.take().expect("no stdin"),
+                Party::Contestant,
+                &transcript,
+            ),
+            forward(
+                interactor.stdout.take().expect("no stdout"),
+                contestant.stdin.take().expect("no stdin"),
+                Party::Interactor,
+                &transcript,
+            ),
+        );
+
+        let (contestant_result, interactor_result) = tokio::join!(
+            reap(contestant_pid, contestant_cgroup.as_ref()),
+            reap(interactor_pid, interactor_cgroup.as_ref())
+        );
+
+        Ok((
+            (contestant_result?, contestant_stderr),
+            (interactor_result?, interactor_stderr),
+        ))
+    };
+
+    let (
+        ((contestant_status, mut contestant_usage), (contestant_stderr, contestant_exceeded)),
+        ((interactor_status, mut interactor_usage), (interactor_stderr, interactor_exceeded)),
+    ) = with_deadline(&profile, &[contestant_pid, interactor_pid], execution).await?;
+
+    let wall_time = start.elapsed();
+    contestant_usage.wall_time = wall_time;
+    contestant_usage.output_limit_exceeded = contestant_exceeded;
+    interactor_usage.wall_time = wall_time;
+    interactor_usage.output_limit_exceeded = interactor_exceeded;
+
+    Ok(Interaction {
+        contestant: Output::new(
+            contestant_status,
+            Vec::new(),
+            contestant_stderr,
+            contestant_usage,
+            contestant_supervisor.and_then(|supervisor| supervisor.denied()),
+        ),
+        interactor: Output::new(
+            interactor_status,
+            Vec::new(),
+            interactor_stderr,
+            interactor_usage,
+            interactor_supervisor.and_then(|supervisor| supervisor.denied()),
+        ),
+        transcript: transcript.into_inner().unwrap().exchanges,
+    })
+}
+
+async fn forward(
+    mut from: impl AsyncRead + Unpin,
+    mut to: impl AsyncWrite + Unpin,
+    party: Party,
+    transcript: &Mutex<Transcript>,
+) {
+    let mut buf = [0; 4096];
+
+    // dropping `to` at the end closes the receiving end's stdin
+    loop {
+        let n = match from.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => {
+                tracing::error!("failed to read from {party:?}: {e}");
+                break;
+            }
+        };
+
+        transcript.lock().unwrap().record(party, &buf[..n]);
+
+        if to.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Namespace isolation for runs: each run gets its own mount, network, PID, IPC and UTS namespaces,
+//! and a minimal read-only root containing only the toolchain (see [`TOOLCHAIN`]) and the
+//! directory it runs in (mounted at `/sandbox`), so that it can't see other submissions, the
+//! contest's test data or the network.
+//!
+//! Since the first process in a PID namespace is its init (which ignores most signals), a run is
+//! three processes deep:
+//!
+//! - the spawned process unshares the namespaces and relays the exit status of the run, so that
+//!   `wait4` works as usual
+//! - its child is the namespace's init, which reaps orphans and reports the run's exit status
+//! - the init's child sets up the root and executes the command
+//!
+//! Everything after `fork` happens between `fork` and `exec`, so it only uses syscalls, with every
+//! path prepared beforehand. Isolation needs `CAP_SYS_ADMIN`; if it is unavailable (or
+//! `JUDGE_NAMESPACES=off` is set), runs fall back to sharing the judge's view of the system.
+
+use std::{
+    env,
+    ffi::{CStr, CString},
+    fs,
+    io::{self, Error},
+    mem,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::Path,
+    process::{Command, Stdio},
+    ptr,
+};
+
+use libc::{c_int, c_ulong, pid_t};
+use once_cell::sync::Lazy;
+
+/// Directories that are made available (read-only) to runs, if they exist
+const TOOLCHAIN: [&str; 8] = [
+    "/bin", "/etc", "/lib", "/lib32", "/lib64", "/opt", "/sbin", "/usr",
+];
+
+/// Devices that are made available to runs
+const DEVICES: [&str; 4] = ["/dev/null", "/dev/random", "/dev/urandom", "/dev/zero"];
+
+/// Whether runs can be isolated, checked once by running `true` in a jail (which blocks, so it
+/// should be forced through [`super::probe`] at startup)
+pub(super) static AVAILABLE: Lazy<bool> = Lazy::new(|| {
+    if env::var("JUDGE_NAMESPACES").is_ok_and(|value| value == "off") {
+        tracing::warn!("namespace isolation disabled by JUDGE_NAMESPACES");
+        return false;
+    }
+
+    let probe = || {
+        let dir = env::temp_dir().join("judge-probe");
+        fs::create_dir_all(&dir)?;
+        let jail = Jail::prepare(&dir)?;
+        let mut cmd = Command::new("true");
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        unsafe { cmd.pre_exec(move || jail.enter()) };
+
+        match cmd.status()?.success() {
+            true => Ok(()),
+            false => Err(Error::other("`true` failed in the jail")),
+        }
+    };
+
+    match probe() {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("namespace isolation unavailable, runs can see the host: {e}");
+            false
+        }
+    }
+});
+
+/// Everything needed to set up the root of a run, prepared before `fork`
+#[derive(Debug)]
+pub struct Jail {
+    root: CString,
+    binds: Vec<(CString, CString)>,
+    symlinks: Vec<(CString, CString)>,
+    dev: CString,
+    devices: Vec<(CString, CString)>,
+    workdir: (CString, CString),
+    tmp: CString,
+    proc: CString,
+}
+
+impl Jail {
+    /// Prepares a jail for a run in `dir`, returning `None` if isolation is unavailable
+    pub fn new(dir: &Path) -> io::Result<Option<Self>> {
+        if !*AVAILABLE {
+            return Ok(None);
+        }
+
+        Jail::prepare(dir).map(Some)
+    }
+
+    fn prepare(dir: &Path) -> io::Result<Self> {
+        // only ever mounted over inside a run's mount namespace, so it stays empty on the host
+        let root = env::temp_dir().join("judge-root");
+        fs::create_dir_all(&root)?;
+
+        let inside = |path: &str| cstring(root.join(path.trim_start_matches('/')));
+        let (mut binds, mut symlinks) = (Vec::new(), Vec::new());
+
+        for path in TOOLCHAIN {
+            match fs::symlink_metadata(path) {
+                // e.g. `/bin -> usr/bin` on a merged `/usr`
+                Ok(metadata) if metadata.is_symlink() => {
+                    symlinks.push((cstring(fs::read_link(path)?)?, inside(path)?))
+                }
+                Ok(metadata) if metadata.is_dir() => binds.push((cstring(path)?, inside(path)?)),
+                _ => {}
+            }
+        }
+
+        let devices = DEVICES
+            .iter()
+            .filter(|device| Path::new(device).exists())
+            .map(|device| Ok((cstring(device)?, inside(device)?)))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Jail {
+            binds,
+            symlinks,
+            dev: inside("/dev")?,
+            devices,
+            workdir: (cstring(dir.canonicalize()?)?, inside("/sandbox")?),
+            tmp: inside("/tmp")?,
+            proc: inside("/proc")?,
+            root: cstring(&root)?,
+        })
+    }
+
+    /// Moves the calling process into new namespaces and a new root, returning only in the
+    /// process that should go on to execute the command
+    ///
+    /// Must only be called between `fork` and `exec`.
+    pub fn enter(&self) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(
+                libc::CLONE_NEWNS
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWPID
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWUTS,
+            ))?;
+
+            let mut status_pipe = [0; 2];
+            check(libc::pipe(status_pipe.as_mut_ptr()))?;
+            let [status_rx, status_tx] = status_pipe;
+
+            // both of the processes above the command leave the run's process group (which is
+            // the one the judge kills) before closing their copy of the pipe that `spawn` waits
+            // on, so they are out of it by the time the judge knows the group exists
+            let init = check(libc::fork())?;
+            if init > 0 {
+                // for the judge's, so that this process survives the command being killed to pass
+                // on the usage of the whole run to `wait4`
+                libc::setpgid(0, libc::getpgid(libc::getppid()));
+                relay(init, status_rx);
+            }
+
+            let command = check(libc::fork())?;
+            if command > 0 {
+                // as a dying init reaps its children without adding up their usage, so it has to
+                // outlive the command for the command's usage to be measured
+                libc::setpgid(0, 0);
+                supervise(command, status_tx);
+            }
+
+            libc::close(status_rx);
+            libc::close(status_tx);
+            self.pivot()
+        }
+    }
+
+    /// Builds the new root out of bind mounts and switches to it
+    unsafe fn pivot(&self) -> io::Result<()> {
+        const READ_ONLY: c_ulong = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+
+        // keeps the mounts below from propagating back to the host
+        mount(None, c"/", None, libc::MS_REC | libc::MS_PRIVATE, None)?;
+        mount(
+            Some(c"tmpfs"),
+            &self.root,
+            Some(c"tmpfs"),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            Some(c"size=1m,mode=755"),
+        )?;
+
+        for (source, target) in &self.binds {
+            check(libc::mkdir(target.as_ptr(), 0o755))?;
+            mount(
+                Some(source),
+                target,
+                None,
+                libc::MS_BIND | libc::MS_REC,
+                None,
+            )?;
+            mount(None, target, None, READ_ONLY | libc::MS_NOSUID, None)?;
+        }
+
+        for (target, link) in &self.symlinks {
+            check(libc::symlink(target.as_ptr(), link.as_ptr()))?;
+        }
+
+        check(libc::mkdir(self.dev.as_ptr(), 0o755))?;
+        for (source, target) in &self.devices {
+            let fd = check(libc::open(
+                target.as_ptr(),
+                libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                0o666,
+            ))?;
+            libc::close(fd);
+            mount(Some(source), target, None, libc::MS_BIND, None)?;
+        }
+
+        let (source, target) = &self.workdir;
+        check(libc::mkdir(target.as_ptr(), 0o755))?;
+        mount(
+            Some(source),
+            target,
+            None,
+            libc::MS_BIND | libc::MS_REC,
+            None,
+        )?;
+        mount(None, target, None, READ_ONLY | libc::MS_NOSUID, None)?;
+
+        check(libc::mkdir(self.tmp.as_ptr(), 0o1777))?;
+        mount(
+            Some(c"tmpfs"),
+            &self.tmp,
+            Some(c"tmpfs"),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            Some(c"size=16m,mode=1777"),
+        )?;
+
+        check(libc::mkdir(self.proc.as_ptr(), 0o555))?;
+        mount(
+            Some(c"proc"),
+            &self.proc,
+            Some(c"proc"),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            None,
+        )?;
+
+        // stacks the old root on top of the new one, and then detaches it
+        check(libc::chdir(self.root.as_ptr()))?;
+        check(libc::syscall(
+            libc::SYS_pivot_root,
+            c".".as_ptr(),
+            c".".as_ptr(),
+        ))?;
+        check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+        mount(None, c"/", None, READ_ONLY, None)?;
+
+        check(libc::chdir(c"/sandbox".as_ptr()))?;
+        check(libc::sethostname(c"sandbox".as_ptr(), "sandbox".len()))?;
+
+        Ok(())
+    }
+}
+
+/// Waits for the namespace's init to report the run's exit status, and exits the same way
+unsafe fn relay(init: pid_t, status_rx: c_int) -> ! {
+    close_all_except(status_rx);
+
+    let mut status: c_int = 0;
+    let reported = read_status(status_rx, &mut status);
+
+    let mut init_status = 0;
+    while libc::waitpid(init, &mut init_status, 0) < 0 && errno() == libc::EINTR {}
+
+    // init exits without reporting anything if the command failed to start
+    exit_like(if reported { status } else { init_status })
+}
+
+/// Reaps every process in the namespace until the command exits, then reports its exit status
+///
+/// Exiting kills anything left in the namespace.
+unsafe fn supervise(command: pid_t, status_tx: c_int) -> ! {
+    close_all_except(status_tx);
+
+    loop {
+        let mut status = 0;
+        match libc::waitpid(-1, &mut status, 0) {
+            pid if pid == command => {
+                libc::write(
+                    status_tx,
+                    ptr::from_ref(&status).cast(),
+                    mem::size_of::<c_int>(),
+                );
+                libc::_exit(0);
+            }
+            -1 if errno() != libc::EINTR => libc::_exit(1),
+            _ => {}
+        }
+    }
+}
+
+unsafe fn read_status(fd: c_int, status: &mut c_int) -> bool {
+    loop {
+        let n = libc::read(fd, ptr::from_mut(status).cast(), mem::size_of::<c_int>());
+        if n < 0 && errno() == libc::EINTR {
+            continue;
+        }
+        return n == mem::size_of::<c_int>() as isize;
+    }
+}
+
+/// Exits with a wait status, by raising the same signal if it was terminated by one
+unsafe fn exit_like(status: c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+
+        let mut set = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Closes every file descriptor except `fd`, including the stdio pipes (so that the judge sees
+/// them close when the command exits) and the pipe `std` uses to report failures to `exec`
+unsafe fn close_all_except(fd: c_int) {
+    for (first, last) in [(0, fd as u32 - 1), (fd as u32 + 1, u32::MAX)] {
+        if libc::syscall(libc::SYS_close_range, first, last, 0) < 0 {
+            // `close_range` was added in Linux 5.9
+            let max = libc::sysconf(libc::_SC_OPEN_MAX).clamp(0, 65536) as u32;
+            for other in first..=last.min(max) {
+                libc::close(other as c_int);
+            }
+        }
+    }
+}
+
+unsafe fn mount(
+    source: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: c_ulong,
+    data: Option<&CStr>,
+) -> io::Result<()> {
+    let ptr = |s: Option<&CStr>| s.map_or(ptr::null(), CStr::as_ptr);
+    check(libc::mount(
+        ptr(source),
+        target.as_ptr(),
+        ptr(fstype),
+        flags,
+        ptr(data).cast(),
+    ))?;
+    Ok(())
+}
+
+fn check<T: Into<i64> + Copy>(result: T) -> io::Result<T> {
+    if result.into() < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn errno() -> c_int {
+    Error::last_os_error().raw_os_error().unwrap_or_default()
+}
+
+fn cstring(path: impl AsRef<Path>) -> io::Result<CString> {
+    CString::new(path.as_ref().as_os_str().as_bytes()).map_err(Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn isolates_runs() {
+        if !*AVAILABLE {
+            eprintln!("skipping, as namespace isolation is unavailable");
+            return;
+        }
+
+        let dir = env::temp_dir().join(format!("judge-jail-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("input.txt"), "visible").unwrap();
+
+        let script = format!(
+            "cat input.txt; echo; \
+             test -e /proc/{judge} && echo 'judge visible'; \
+             touch /tmp/file && echo 'tmp writable'; \
+             touch /sandbox/file 2>/dev/null && echo 'sandbox writable'; \
+             touch /etc/file 2>/dev/null && echo 'root writable'; \
+             true",
+            judge = std::process::id()
+        );
+        let jail = Jail::prepare(&dir).unwrap();
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", &script]).current_dir(&dir);
+        unsafe { cmd.pre_exec(move || jail.enter()) };
+        let output = cmd.output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "visible\ntmp writable\n"
+        );
+    }
+}
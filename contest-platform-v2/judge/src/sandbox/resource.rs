@@ -4,7 +4,7 @@ use rlimit::{setrlimit, Resource};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct ResourceUsage {
     /// User time
@@ -13,6 +13,15 @@ pub struct ResourceUsage {
     pub sys_time: Duration,
     /// Memory usage (bytes)
     pub memory: u64,
+    /// Wall clock time
+    pub wall_time: Duration,
+    /// Whether the kernel killed the process for running out of memory (cgroups only)
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// Whether the judge killed the process for writing more than the output limit to stdout or
+    /// stderr
+    #[serde(default)]
+    pub output_limit_exceeded: bool,
 }
 
 impl ResourceUsage {
@@ -25,34 +34,183 @@ impl ResourceUsage {
     }
 
     pub fn exceeded_time(&self, resource_limits: ResourceLimits) -> bool {
-        (self.total_time().as_secs_f64() - resource_limits.cpu as f64).abs()
-            <= resource_limits.cpu_tolerance
+        self.total_time().as_secs_f64() > resource_limits.cpu + resource_limits.cpu_tolerance
     }
 
+    /// Whether the memory usage reached the limit, give or take the tolerance (a failed allocation
+    /// never shows up in the usage, so a process stopped by the limit can measure just under it)
     pub fn exceeded_memory(&self, resource_limits: ResourceLimits) -> bool {
-        self.memory.abs_diff(resource_limits.memory) <= resource_limits.memory_tolerance
+        self.oom_killed
+            || self.memory.saturating_add(resource_limits.memory_tolerance)
+                >= resource_limits.memory
+    }
+
+    pub fn exceeded_wall_time(&self, resource_limits: ResourceLimits) -> bool {
+        self.wall_time >= resource_limits.wall_time_limit()
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub struct ResourceLimits {
     /// CPU time (seconds)
-    pub cpu: u64,
+    pub cpu: f64,
     /// CPU time tolerance (seconds)
     pub cpu_tolerance: f64,
     /// Memory usage (bytes)
     pub memory: u64,
     /// Memory usage tolerance (bytes)
     pub memory_tolerance: u64,
+    /// Wall clock time (seconds), defaults to twice the CPU time plus one second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall_time: Option<f64>,
+    /// Size of stdout, stderr and each file written (bytes), defaults to 64 MiB
+    #[serde(default = "default_output")]
+    pub output: u64,
+}
+
+fn default_output() -> u64 {
+    64 * 1024 * 1024
 }
 
 impl ResourceLimits {
-    pub fn set(&self) -> io::Result<()> {
-        setrlimit(Resource::CPU, self.cpu, self.cpu)?;
-        setrlimit(Resource::DATA, self.memory, self.memory)?;
+    pub fn wall_time_limit(&self) -> Duration {
+        self.wall_time
+            .map(Duration::from_secs_f64)
+            .unwrap_or_else(|| Duration::from_secs_f64(self.cpu * 2.0 + 1.0))
+    }
+
+    /// Most memory a process can use, which is what both the cgroup and the rlimit cap it at
+    ///
+    /// This includes the tolerance, which [`ResourceUsage::exceeded_memory`] relies on.
+    pub fn memory_cap(&self) -> u64 {
+        self.memory.saturating_add(self.memory_tolerance)
+    }
+
+    /// Sets the rlimits for the calling process, leaving memory to the cgroup if there is one
+    pub fn set(&self, limit_memory: bool) -> io::Result<()> {
+        // the CPU time limit can only be set in whole seconds, so processes that exceed a
+        // fractional limit are only caught once they exit
+        let cpu = (self.cpu + self.cpu_tolerance).ceil() as u64;
+        setrlimit(Resource::CPU, cpu, cpu)?;
+        if limit_memory {
+            let memory = self.memory_cap();
+            setrlimit(Resource::DATA, memory, memory)?;
+        }
+        setrlimit(Resource::FSIZE, self.output, self.output)?;
         Ok(())
     }
+
+    /// The limit that stopped the process, if it was stopped by one
+    ///
+    /// The termination signal decides whenever it can. `SIGKILL` comes from the hard CPU time
+    /// limit, the cgroup's OOM killer or the judge itself, while running out of memory under
+    /// rlimits usually shows up as a crash (e.g. dereferencing a failed `malloc`) or an abort (e.g.
+    /// an uncaught `std::bad_alloc`), so those are only put down to a limit if the usage agrees.
+    pub fn exceeded(&self, exit_status: ExitStatus, usage: ResourceUsage) -> Option<Limit> {
+        match exit_status.signal() {
+            _ if usage.output_limit_exceeded => Some(Limit::Output),
+            Some(libc::SIGXFSZ) => Some(Limit::Output),
+            Some(libc::SIGXCPU) => Some(Limit::Time),
+            // checked even if the process exited normally, as time limits can be fractional
+            _ if usage.exceeded_time(*self) => Some(Limit::Time),
+            None => None,
+            _ if usage.oom_killed => Some(Limit::Memory),
+            Some(libc::SIGKILL) if usage.exceeded_wall_time(*self) => Some(Limit::WallTime),
+            Some(libc::SIGKILL | libc::SIGSEGV | libc::SIGBUS | libc::SIGABRT)
+                if usage.exceeded_memory(*self) =>
+            {
+                Some(Limit::Memory)
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// The resource limit that stopped a run
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Limit {
+    Time,
+    Memory,
+    Output,
+    WallTime,
+}
+
+/// Limits for compiling a submission or helper program, which are much looser than the limits for
+/// running one, but stop a malicious submission (e.g. a template bomb) from pinning the host
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CompileLimits {
+    /// CPU time (seconds)
+    pub cpu: f64,
+    /// Memory usage (bytes)
+    pub memory: u64,
+    /// Size of stdout, stderr and each file written by the compiler (bytes)
+    pub output: u64,
+    /// Wall clock time (seconds), defaults to twice the CPU time plus one second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wall_time: Option<f64>,
+}
+
+/// The compile limit that stopped a compiler
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum CompileLimit {
+    Time,
+    Memory,
+    Output,
+}
+
+impl Default for CompileLimits {
+    fn default() -> Self {
+        CompileLimits {
+            cpu: 10.0,
+            memory: 1024 * 1024 * 1024,
+            output: 64 * 1024 * 1024,
+            wall_time: None,
+        }
+    }
+}
+
+impl CompileLimits {
+    pub fn wall_time_limit(&self) -> Duration {
+        self.wall_time
+            .map(Duration::from_secs_f64)
+            .unwrap_or_else(|| Duration::from_secs_f64(self.cpu * 2.0 + 1.0))
+    }
+
+    /// Sets the rlimits for the calling process, leaving memory to the cgroup if there is one
+    pub fn set(&self, limit_memory: bool) -> io::Result<()> {
+        let cpu = self.cpu.ceil() as u64;
+        setrlimit(Resource::CPU, cpu, cpu)?;
+        if limit_memory {
+            setrlimit(Resource::DATA, self.memory, self.memory)?;
+        }
+        setrlimit(Resource::FSIZE, self.output, self.output)?;
+        Ok(())
+    }
+
+    /// The limit that stopped the compiler, if it failed because of one
+    ///
+    /// Compiler drivers (e.g. `gcc`) usually report a limit hit by one of their subprocesses as an
+    /// ordinary failure, so the measured usage is checked as well as the termination signal.
+    pub fn exceeded(&self, exit_status: ExitStatus, usage: ResourceUsage) -> Option<CompileLimit> {
+        if exit_status.success() {
+            return None;
+        }
+
+        match exit_status.signal() {
+            Some(libc::SIGXCPU) => Some(CompileLimit::Time),
+            Some(libc::SIGXFSZ) => Some(CompileLimit::Output),
+            _ if usage.output_limit_exceeded => Some(CompileLimit::Output),
+            _ if usage.total_time().as_secs_f64() >= self.cpu
+                || usage.wall_time >= self.wall_time_limit() =>
+            {
+                Some(CompileLimit::Time)
+            }
+            _ if usage.oom_killed || usage.memory >= self.memory => Some(CompileLimit::Memory),
+            _ => None,
+        }
+    }
 }
 
 pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
@@ -65,7 +223,6 @@ pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
         Err(io::Error::last_os_error())
     } else {
         let rusage = unsafe { rusage.assume_init() };
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
 
         let convert = |timeval: libc::timeval| {
             let duration = timeval.tv_sec * 1_000_000 + timeval.tv_usec;
@@ -77,8 +234,89 @@ pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
             ResourceUsage {
                 user_time: convert(rusage.ru_utime),
                 sys_time: convert(rusage.ru_stime),
-                memory: (rusage.ru_maxrss * page_size) as u64,
+                // `ru_maxrss` is in KiB
+                memory: rusage.ru_maxrss as u64 * 1024,
+                wall_time: Duration::ZERO,
+                oom_killed: false,
+                output_limit_exceeded: false,
             },
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    const LIMITS: ResourceLimits = ResourceLimits {
+        cpu: 1.0,
+        cpu_tolerance: 0.1,
+        memory: 256 * MIB,
+        memory_tolerance: MIB,
+        // three seconds
+        wall_time: None,
+        output: MIB,
+    };
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn killed(signal: i32) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    fn usage(cpu_ms: u64, memory: u64, wall_ms: u64) -> ResourceUsage {
+        ResourceUsage {
+            user_time: Duration::from_millis(cpu_ms),
+            memory,
+            wall_time: Duration::from_millis(wall_ms),
+            ..ResourceUsage::default()
+        }
+    }
+
+    #[test]
+    fn exceeded() {
+        let oom_killed = ResourceUsage {
+            oom_killed: true,
+            ..usage(100, 200 * MIB, 200)
+        };
+        let output_exceeded = ResourceUsage {
+            output_limit_exceeded: true,
+            ..usage(100, MIB, 200)
+        };
+
+        #[rustfmt::skip]
+        let cases = [
+            ("within limits", exited(0), usage(500, 100 * MIB, 600), None),
+            ("runtime error", exited(1), usage(500, 100 * MIB, 600), None),
+            ("crash", killed(libc::SIGSEGV), usage(500, 100 * MIB, 600), None),
+            ("abort", killed(libc::SIGABRT), usage(500, 100 * MIB, 600), None),
+            ("broken pipe", killed(libc::SIGPIPE), usage(500, 256 * MIB, 600), None),
+            ("killed within limits", killed(libc::SIGKILL), usage(500, 100 * MIB, 600), None),
+            ("within time tolerance", exited(0), usage(1050, MIB, 1100), None),
+            ("over fractional time", exited(0), usage(1200, MIB, 1300), Some(Limit::Time)),
+            ("over time with error", exited(1), usage(1200, MIB, 1300), Some(Limit::Time)),
+            ("soft CPU limit", killed(libc::SIGXCPU), usage(2000, MIB, 2100), Some(Limit::Time)),
+            ("hard CPU limit", killed(libc::SIGKILL), usage(2500, MIB, 2600), Some(Limit::Time)),
+            ("far over time", killed(libc::SIGKILL), usage(60_000, MIB, 60_000), Some(Limit::Time)),
+            ("idle", killed(libc::SIGKILL), usage(10, MIB, 3000), Some(Limit::WallTime)),
+            ("OOM killed", killed(libc::SIGKILL), oom_killed, Some(Limit::Memory)),
+            ("crash at memory limit", killed(libc::SIGSEGV), usage(500, 256 * MIB, 600), Some(Limit::Memory)),
+            ("crash near memory limit", killed(libc::SIGSEGV), usage(500, 255 * MIB + 1, 600), Some(Limit::Memory)),
+            ("crash far over memory limit", killed(libc::SIGSEGV), usage(500, 1024 * MIB, 600), Some(Limit::Memory)),
+            ("bad_alloc", killed(libc::SIGABRT), usage(500, 256 * MIB, 600), Some(Limit::Memory)),
+            ("bus error at memory limit", killed(libc::SIGBUS), usage(500, 256 * MIB, 600), Some(Limit::Memory)),
+            ("killed at memory limit", killed(libc::SIGKILL), usage(500, 256 * MIB, 600), Some(Limit::Memory)),
+            ("exited at memory limit", exited(1), usage(500, 256 * MIB, 600), None),
+            ("output limit", killed(libc::SIGKILL), output_exceeded, Some(Limit::Output)),
+            ("file size limit", killed(libc::SIGXFSZ), usage(100, MIB, 200), Some(Limit::Output)),
+        ];
+
+        for (name, exit_status, usage, expected) in cases {
+            assert_eq!(LIMITS.exceeded(exit_status, usage), expected, "{name}");
+        }
+    }
+}
@@ -1,153 +1,423 @@
-use std::env::consts::ARCH;
+//! Seccomp filters, which restrict the syscalls that compilers and runs can make.
+//!
+//! Runs are filtered by an allowlist, given by a named profile (see [`SeccompProfile`]) that each
+//! language refers to. Profiles are compiled the first time they are used, and the compiled
+//! filters are shared by every contest with the same rules.
+//!
+//! A denied syscall fails with `EPERM` by default, but a profile can instead have the run killed,
+//! so that the judge can tell the contestant which syscall was to blame. A profile in trace mode
+//! allows every syscall, but logs the ones it would deny. Either way, the run's filter hands
+//! mismatches to the judge through a seccomp user notification listener, which the run sends to
+//! the judge right after installing the filter (see [`Supervisor`]).
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    env::consts::ARCH,
+    io::{self, Error, Write},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    ptr,
+    sync::{Arc, Mutex, OnceLock},
+};
 
 use libc::*;
 use once_cell::sync::Lazy;
+use schemars::JsonSchema;
 use seccompiler::{
-    BpfProgram, Result, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    BackendError, BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
     SeccompFilter, SeccompRule,
 };
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::Profile;
+
+mod syscalls;
+
+/// Profile used by languages that don't name one
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Profiles that every contest can use, unless it defines one with the same name
+static BUILT_IN: Lazy<BTreeMap<&str, SeccompProfile>> = Lazy::new(|| {
+    [(DEFAULT_PROFILE, include_str!("seccomp/default.toml"))]
+        .into_iter()
+        .map(|(name, src)| {
+            let profile = toml::from_str(src).expect("invalid built-in seccomp profile");
+            (name, profile)
+        })
+        .collect()
+});
+
+/// Compiled filters, by the rules they were compiled from
+static FILTERS: Lazy<Mutex<BTreeMap<Policy, Arc<SyscallFilter>>>> = Lazy::new(Default::default);
+
+// not in `libc` yet (see <linux/seccomp.h>)
+const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+const SECCOMP_IOCTL_NOTIF_RECV: u64 = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: u64 = 0xc018_2101;
+const BPF_RET_K: u16 = 0x06;
+
+/// The syscalls that runs in a language may make
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SeccompProfile {
+    /// Profile whose syscalls are also allowed (e.g. `default`, the built-in profile for C and C++)
+    pub extends: Option<String>,
+    /// Names of the syscalls that are always allowed
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Syscalls that are only allowed with certain arguments
+    #[serde(default)]
+    pub rules: Vec<SyscallRule>,
+    /// What happens when a run makes a syscall that isn't allowed (the default is `errno`)
+    #[serde(default)]
+    pub on_denied: OnDenied,
+    /// Allows every syscall, logging the ones that the profile would deny (for building a profile
+    /// for a new runtime, never for a live contest)
+    #[serde(default)]
+    pub trace: bool,
+}
+
+/// What happens when a run makes a syscall that its profile doesn't allow
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnDenied {
+    /// The syscall fails with `EPERM`, which most programs report as a runtime error
+    #[default]
+    Errno,
+    /// The run is killed, and the syscall is reported with a restricted function verdict
+    Kill,
+}
+
+/// Allows a syscall when all of the conditions on its arguments hold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SyscallRule {
+    pub syscall: String,
+    pub args: Vec<ArgCondition>,
+}
+
+/// A comparison of one of a syscall's arguments with a value
+#[derive(
+    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+pub struct ArgCondition {
+    /// Position of the argument (from 0)
+    pub index: u8,
+    #[serde(default)]
+    pub op: ArgOp,
+    pub value: u64,
+    #[serde(default)]
+    pub width: ArgWidth,
+}
+
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgOp {
+    #[default]
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// How much of an argument is compared
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgWidth {
+    /// The lower 32 bits, for `int` arguments (e.g. flags), as the kernel ignores the upper ones
+    /// and a program could set them to anything
+    Dword,
+    /// All 64 bits, for pointers, sizes and other `long` arguments
+    #[default]
+    Qword,
+}
+
+#[derive(Debug, Error)]
+pub enum SeccompError {
+    #[error("unknown seccomp profile {0}")]
+    UnknownProfile(String),
+    #[error("seccomp profile {0} extends itself")]
+    Cycle(String),
+    #[error("seccomp profile {profile}: unknown syscall {syscall}")]
+    UnknownSyscall { profile: String, syscall: String },
+    #[error("seccomp profile {profile}: {source}")]
+    Compile {
+        profile: String,
+        source: BackendError,
+    },
+}
+
+/// A profile with everything it extends merged in, mapping syscall numbers to the conditions that
+/// allow them (where no conditions always allow it)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Policy {
+    profile: String,
+    rules: BTreeMap<c_long, BTreeSet<Vec<ArgCondition>>>,
+    mode: Mode,
+}
+
+/// How a filter handles the syscalls that its profile doesn't allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Mode {
+    Errno,
+    Kill,
+    Trace,
+}
+
+/// A compiled profile, ready to be installed in a run
+#[derive(Debug, PartialEq)]
+pub struct SyscallFilter {
+    profile: String,
+    program: BpfProgram,
+    mode: Mode,
+}
+
+impl SyscallFilter {
+    /// Filter for the profile called `name`, which is looked up in `profiles` and then in the
+    /// built-in profiles
+    pub fn new(
+        name: &str,
+        profiles: &BTreeMap<String, SeccompProfile>,
+    ) -> Result<Arc<Self>, SeccompError> {
+        let policy = Policy::resolve(name, profiles)?;
+
+        match FILTERS.lock().unwrap().entry(policy) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                let filter = SyscallFilter::compile(entry.key()).map_err(|source| {
+                    SeccompError::Compile {
+                        profile: name.to_owned(),
+                        source,
+                    }
+                })?;
+                Ok(entry.insert(Arc::new(filter)).clone())
+            }
+        }
+    }
+
+    fn compile(policy: &Policy) -> Result<Self, BackendError> {
+        let mut rules = BTreeMap::new();
+        for (&syscall, conditions) in &policy.rules {
+            // a rule without conditions already allows every call
+            let syscall_rules = if conditions.contains(&Vec::new()) {
+                Vec::new()
+            } else {
+                conditions
+                    .iter()
+                    .map(|conditions| {
+                        let conditions = conditions
+                            .iter()
+                            .map(ArgCondition::compile)
+                            .collect::<Result<_, _>>()?;
+                        SeccompRule::new(conditions)
+                    })
+                    .collect::<Result<_, _>>()?
+            };
+            rules.insert(syscall, syscall_rules);
+        }
+
+        if policy.mode == Mode::Trace {
+            tracing::warn!(
+                "seccomp profile {} is in trace mode, so runs can make any syscall",
+                policy.profile
+            );
+        }
+
+        let mismatch = if policy.mode == Mode::Errno {
+            SeccompAction::Errno(EPERM as u32)
+        } else {
+            // needed to hand the listener to the judge, before anyone is listening
+            rules.insert(SYS_read, Vec::new());
+            rules.insert(SYS_write, Vec::new());
+            // `seccompiler` has no user notification action, so it's patched in below
+            SeccompAction::Trace(0)
+        };
+
+        let filter = SeccompFilter::new(
+            rules,
+            mismatch,
+            SeccompAction::Allow,
+            ARCH.try_into().expect("unsupported architecture"),
+        )?;
+        let mut program: BpfProgram = filter.try_into()?;
+
+        if policy.mode != Mode::Errno {
+            for instruction in &mut program {
+                if instruction.code == BPF_RET_K && instruction.k == SECCOMP_RET_TRACE {
+                    instruction.k = SECCOMP_RET_USER_NOTIF;
+                }
+            }
+        }
+
+        Ok(SyscallFilter {
+            profile: policy.profile.clone(),
+            program,
+            mode: policy.mode,
+        })
+    }
+}
+
+impl Policy {
+    fn resolve(
+        name: &str,
+        profiles: &BTreeMap<String, SeccompProfile>,
+    ) -> Result<Self, SeccompError> {
+        let mut policy = Policy {
+            profile: name.to_owned(),
+            rules: BTreeMap::new(),
+            mode: Mode::Errno,
+        };
+
+        let mut seen = Vec::new();
+        let mut next = Some(name);
+        while let Some(name) = next {
+            if seen.contains(&name) {
+                return Err(SeccompError::Cycle(name.to_owned()));
+            }
+            seen.push(name);
+
+            let profile = profiles
+                .get(name)
+                .or_else(|| BUILT_IN.get(name))
+                .ok_or_else(|| SeccompError::UnknownProfile(name.to_owned()))?;
+            // only the profile that was asked for decides what happens to denied syscalls
+            if seen.len() == 1 {
+                policy.mode = match (profile.trace, profile.on_denied) {
+                    (true, _) => Mode::Trace,
+                    (false, OnDenied::Errno) => Mode::Errno,
+                    (false, OnDenied::Kill) => Mode::Kill,
+                };
+            }
+
+            let allowed = profile.allow.iter().map(|syscall| (syscall, &[][..]));
+            let rules = profile
+                .rules
+                .iter()
+                .map(|rule| (&rule.syscall, &rule.args[..]));
+
+            for (syscall, args) in allowed.chain(rules) {
+                let number =
+                    syscalls::number(syscall).ok_or_else(|| SeccompError::UnknownSyscall {
+                        profile: name.to_owned(),
+                        syscall: syscall.clone(),
+                    })?;
+                policy
+                    .rules
+                    .entry(number)
+                    .or_default()
+                    .insert(args.to_vec());
+            }
+
+            next = profile.extends.as_deref();
+        }
 
-static SECCOMP_FILTER: Lazy<BpfProgram> = Lazy::new(|| {
+        Ok(policy)
+    }
+}
+
+impl ArgCondition {
+    fn compile(&self) -> Result<SeccompCondition, BackendError> {
+        let op = match self.op {
+            ArgOp::Eq => SeccompCmpOp::Eq,
+            ArgOp::Ne => SeccompCmpOp::Ne,
+            ArgOp::Lt => SeccompCmpOp::Lt,
+            ArgOp::Le => SeccompCmpOp::Le,
+            ArgOp::Gt => SeccompCmpOp::Gt,
+            ArgOp::Ge => SeccompCmpOp::Ge,
+        };
+
+        let width = match self.width {
+            ArgWidth::Dword => SeccompCmpArgLen::Dword,
+            ArgWidth::Qword => SeccompCmpArgLen::Qword,
+        };
+
+        SeccompCondition::new(self.index, width, op, self.value)
+    }
+}
+
+/// Compilers fork, write files and use far more of the kernel than submissions, so instead of an
+/// allowlist, only syscalls that a compiler has no business making are denied
+static COMPILE_FILTER: Lazy<BpfProgram> = Lazy::new(|| {
     let filter = SeccompFilter::new(
         [
-            (SYS_access, vec![]),
-            (SYS_arch_prctl, vec![]),
-            (SYS_brk, vec![]),
-            (SYS_clock_getres, vec![]),
-            (SYS_clock_gettime, vec![]),
-            (SYS_clone3, vec![]),
-            (SYS_clone, vec![]),
-            (SYS_close, vec![]),
-            (SYS_dup2, vec![]),
-            (SYS_dup3, vec![]),
-            (SYS_dup, vec![]),
-            (SYS_clock_gettime, vec![]),
-            (SYS_clock_nanosleep, vec![]),
-            (SYS_epoll_create1, vec![]),
-            (SYS_epoll_create, vec![]),
-            (SYS_epoll_ctl, vec![]),
-            (SYS_epoll_pwait, vec![]),
-            (SYS_epoll_wait, vec![]),
-            (SYS_execve, vec![]),
-            (SYS_exit_group, vec![]),
-            (SYS_exit, vec![]),
-            (SYS_fcntl, vec![]),
-            (SYS_fstat, vec![]),
-            (SYS_futex, vec![]),
-            (SYS_getcwd, vec![]),
-            (SYS_getdents64, vec![]),
-            (SYS_getdents, vec![]),
-            (SYS_getegid, vec![]),
-            (SYS_geteuid, vec![]),
-            (SYS_getgid, vec![]),
-            (SYS_getpgrp, vec![]),
-            (SYS_getpid, vec![]),
-            (SYS_getppid, vec![]),
-            (SYS_getrandom, vec![]),
-            (SYS_getrlimit, vec![]),
-            (SYS_getrusage, vec![]),
-            (SYS_gettid, vec![]),
-            (SYS_gettimeofday, vec![]),
-            (SYS_getuid, vec![]),
-            (SYS_ioctl, vec![]),
-            (SYS_lseek, vec![]),
-            (SYS_madvise, vec![]),
-            (SYS_mmap, vec![]),
-            (SYS_modify_ldt, vec![]),
-            (SYS_mprotect, vec![]),
-            (SYS_mremap, vec![]),
-            (SYS_munmap, vec![]),
-            (SYS_nanosleep, vec![]),
-            (SYS_newfstatat, vec![]),
-            (
-                SYS_open,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_LARGEFILE | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (
-                SYS_openat,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_NONBLOCK | O_CLOEXEC | O_DIRECTORY) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (SYS_pipe2, vec![]),
-            (SYS_pipe, vec![]),
-            (SYS_poll, vec![]),
-            (SYS_ppoll, vec![]),
-            (SYS_pread64, vec![]),
-            (SYS_readlinkat, vec![]),
-            (SYS_readlink, vec![]),
-            (SYS_read, vec![]),
-            (SYS_restart_syscall, vec![]),
-            (SYS_rseq, vec![]),
-            (SYS_rt_sigaction, vec![]),
-            (SYS_rt_sigprocmask, vec![]),
-            (SYS_rt_sigreturn, vec![]),
-            (SYS_sched_getaffinity, vec![]),
-            (SYS_sched_getparam, vec![]),
-            (SYS_sched_get_priority_max, vec![]),
-            (SYS_sched_get_priority_min, vec![]),
-            (SYS_sched_getscheduler, vec![]),
-            (SYS_sched_setscheduler, vec![]),
-            (SYS_sched_yield, vec![]),
-            (SYS_select, vec![]),
-            (SYS_set_robust_list, vec![]),
-            (SYS_set_thread_area, vec![]),
-            (SYS_set_tid_address, vec![]),
-            (SYS_sigaltstack, vec![]),
-            (SYS_statfs, vec![]),
-            (SYS_sysinfo, vec![]),
-            (SYS_timer_create, vec![]),
-            (SYS_timer_delete, vec![]),
-            (SYS_timerfd_create, vec![]),
-            (SYS_timer_settime, vec![]),
-            (SYS_time, vec![]),
-            (SYS_uname, vec![]),
-            (SYS_write, vec![]),
-            (SYS_writev, vec![]),
+            (SYS_accept4, vec![]),
+            (SYS_accept, vec![]),
+            (SYS_acct, vec![]),
+            (SYS_add_key, vec![]),
+            (SYS_adjtimex, vec![]),
+            (SYS_bind, vec![]),
+            (SYS_bpf, vec![]),
+            (SYS_chroot, vec![]),
+            (SYS_clock_settime, vec![]),
+            (SYS_connect, vec![]),
+            (SYS_delete_module, vec![]),
+            (SYS_finit_module, vec![]),
+            (SYS_init_module, vec![]),
+            (SYS_ioperm, vec![]),
+            (SYS_iopl, vec![]),
+            (SYS_kexec_load, vec![]),
+            (SYS_keyctl, vec![]),
+            (SYS_listen, vec![]),
+            (SYS_mount, vec![]),
+            (SYS_perf_event_open, vec![]),
+            (SYS_pivot_root, vec![]),
+            (SYS_process_vm_readv, vec![]),
+            (SYS_process_vm_writev, vec![]),
+            (SYS_ptrace, vec![]),
+            (SYS_quotactl, vec![]),
+            (SYS_reboot, vec![]),
+            (SYS_request_key, vec![]),
+            (SYS_setdomainname, vec![]),
+            (SYS_setgid, vec![]),
+            (SYS_setgroups, vec![]),
+            (SYS_sethostname, vec![]),
+            (SYS_setns, vec![]),
+            (SYS_setregid, vec![]),
+            (SYS_setresgid, vec![]),
+            (SYS_setresuid, vec![]),
+            (SYS_setreuid, vec![]),
+            (SYS_settimeofday, vec![]),
+            (SYS_setuid, vec![]),
+            (SYS_socket, vec![]),
+            (SYS_swapoff, vec![]),
+            (SYS_swapon, vec![]),
+            (SYS_umount2, vec![]),
+            (SYS_unshare, vec![]),
         ]
         .into(),
-        SeccompAction::Errno(EPERM as u32),
         SeccompAction::Allow,
+        SeccompAction::Errno(EPERM as u32),
         ARCH.try_into().expect("unsupported architecture"),
     )
     .expect("failed to create seccomp filter");
@@ -155,7 +425,272 @@ static SECCOMP_FILTER: Lazy<BpfProgram> = Lazy::new(|| {
     filter.try_into().expect("failed to compile seccomp filter")
 });
 
-pub fn apply_filters() -> Result<()> {
-    seccompiler::apply_filter(&SECCOMP_FILTER)?;
+/// Installs the profile's filter, handing its listener to the judge through `supervisor` (the
+/// run's end of the [`Supervisor`]) if it has one
+///
+/// Must only be called between `fork` and `exec`.
+pub fn apply_filters(profile: &Profile, supervisor: Option<RawFd>) -> io::Result<()> {
+    let filter = match profile {
+        Profile::Compile(_) => &COMPILE_FILTER,
+        Profile::Run(_, filter) => &filter.program,
+    };
+
+    match supervisor {
+        Some(supervisor) => unsafe { listen(filter, supervisor) },
+        None => seccompiler::apply_filter(filter).map_err(Error::other),
+    }
+}
+
+/// Installs a filter with a user notification listener, and waits for the judge to take a copy
+/// of the listener (which is closed on `exec`)
+unsafe fn listen(filter: &BpfProgram, supervisor: RawFd) -> io::Result<()> {
+    let program = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr().cast_mut().cast(),
+    };
+
+    check(prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) as c_long)?;
+    let listener = check(syscall(
+        SYS_seccomp,
+        SECCOMP_SET_MODE_FILTER,
+        SECCOMP_FILTER_FLAG_NEW_LISTENER,
+        ptr::from_ref(&program),
+    ))? as c_int;
+
+    // the judge reads the PID this is sent from off the socket, to copy the listener from it
+    let sent = write(
+        supervisor,
+        ptr::from_ref(&listener).cast(),
+        mem::size_of::<c_int>(),
+    );
+    let mut ack = 0u8;
+    if sent < 0 || read(supervisor, ptr::from_mut(&mut ack).cast(), 1) != 1 {
+        return Err(Error::other("the judge didn't take the seccomp listener"));
+    }
+
     Ok(())
 }
+
+/// Handles the syscalls that a run's profile doesn't allow, if its filter hands them to the judge
+///
+/// The supervisor must be started before the run is spawned (which waits for the run to `exec`),
+/// and told once it has been, so that the judge notices if the run fails before installing its
+/// filter.
+#[derive(Debug)]
+pub struct Supervisor {
+    run: Option<UnixStream>,
+    denied: Arc<OnceLock<String>>,
+}
+
+impl Supervisor {
+    /// Starts supervising in the background, if the profile's filter needs it
+    pub fn start(profile: &Profile) -> io::Result<Option<Self>> {
+        let Profile::Run(_, filter) = profile else {
+            return Ok(None);
+        };
+        if filter.mode == Mode::Errno {
+            return Ok(None);
+        }
+
+        let (judge, run) = UnixStream::pair()?;
+        // credentials give the PID of the run, as seen from the judge's PID namespace
+        let enable: c_int = 1;
+        check(unsafe {
+            setsockopt(
+                judge.as_raw_fd(),
+                SOL_SOCKET,
+                SO_PASSCRED,
+                ptr::from_ref(&enable).cast(),
+                mem::size_of::<c_int>() as socklen_t,
+            )
+        } as c_long)?;
+
+        let denied = Arc::new(OnceLock::new());
+        let (mode, first_denied) = (filter.mode, denied.clone());
+        let (profile, span) = (filter.profile.clone(), tracing::Span::current());
+        tokio::task::spawn_blocking(move || {
+            let _guard = span.enter();
+            match supervise(judge, mode, &first_denied) {
+                Ok(traced) if traced.is_empty() => {}
+                Ok(traced) => {
+                    let mut names: Vec<_> = traced.into_iter().map(name).collect();
+                    names.sort_unstable();
+                    tracing::warn!("seccomp profile {profile} would deny {}", names.join(", "));
+                }
+                Err(e) => tracing::error!("failed to supervise seccomp profile {profile}: {e}"),
+            }
+        });
+
+        Ok(Some(Supervisor {
+            run: Some(run),
+            denied,
+        }))
+    }
+
+    /// The run's end of the supervisor, for [`apply_filters`]
+    pub fn run_fd(&self) -> RawFd {
+        self.run.as_ref().map_or(-1, AsRawFd::as_raw_fd)
+    }
+
+    /// Closes the judge's copy of the run's end, once the run has been spawned
+    pub fn spawned(&mut self) {
+        self.run = None;
+    }
+
+    /// Syscall that the run was killed for making, if any
+    pub fn denied(&self) -> Option<String> {
+        self.denied.get().cloned()
+    }
+}
+
+/// Takes the run's listener and handles notified syscalls until every process in the run has
+/// exited, returning the syscalls that were allowed in trace mode
+fn supervise(
+    mut judge: UnixStream,
+    mode: Mode,
+    denied: &OnceLock<String>,
+) -> io::Result<BTreeSet<c_long>> {
+    let mut traced = BTreeSet::new();
+    let Some((pid, fd)) = receive(&judge)? else {
+        // the run failed before installing the filter
+        return Ok(traced);
+    };
+
+    let listener = unsafe {
+        let pidfd = OwnedFd::from_raw_fd(check(syscall(SYS_pidfd_open, pid, 0))? as c_int);
+        OwnedFd::from_raw_fd(check(syscall(SYS_pidfd_getfd, pidfd.as_raw_fd(), fd, 0))? as c_int)
+    };
+    judge.write_all(&[0])?;
+    drop(judge);
+
+    loop {
+        let mut poll_fd = pollfd {
+            fd: listener.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        if unsafe { poll(&mut poll_fd, 1, -1) } < 0 {
+            match Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            }
+        }
+        // otherwise the listener hung up, as no process uses the filter anymore
+        if poll_fd.revents & POLLIN == 0 {
+            return Ok(traced);
+        }
+
+        let mut notification: seccomp_notif = unsafe { mem::zeroed() };
+        if unsafe {
+            ioctl(
+                listener.as_raw_fd(),
+                SECCOMP_IOCTL_NOTIF_RECV,
+                &mut notification,
+            )
+        } < 0
+        {
+            match Error::last_os_error().raw_os_error() {
+                // the process was killed while making the syscall
+                Some(ENOENT | EINTR) => continue,
+                _ => return Err(Error::last_os_error()),
+            }
+        }
+
+        let syscall = notification.data.nr as c_long;
+        let response = match mode {
+            Mode::Trace => {
+                traced.insert(syscall);
+                seccomp_notif_resp {
+                    id: notification.id,
+                    val: 0,
+                    error: 0,
+                    flags: SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32,
+                }
+            }
+            _ => {
+                // recorded before the kill, so it's there by the time the judge reaps the run
+                denied.get_or_init(|| name(syscall));
+                kill_run(notification.pid as pid_t);
+                seccomp_notif_resp {
+                    id: notification.id,
+                    val: 0,
+                    error: -EPERM,
+                    flags: 0,
+                }
+            }
+        };
+
+        if unsafe { ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_SEND, &response) } < 0
+            && Error::last_os_error().raw_os_error() != Some(ENOENT)
+        {
+            return Err(Error::last_os_error());
+        }
+    }
+}
+
+/// Kills the process group of a run's process, which is the whole run unless it's jailed (in
+/// which case killing the namespace's init takes out the rest)
+fn kill_run(pid: pid_t) {
+    unsafe {
+        let group = getpgid(pid);
+        if group > 0 && group != getpgrp() {
+            killpg(group, SIGKILL);
+        } else {
+            kill(pid, SIGKILL);
+        }
+    }
+}
+
+fn name(syscall: c_long) -> String {
+    match syscalls::name(syscall) {
+        Some(name) => name.to_owned(),
+        None => format!("#{syscall}"),
+    }
+}
+
+/// Receives the number of the listener's file descriptor in the run, along with the run's PID,
+/// returning `None` if the run closed the socket without sending anything
+fn receive(judge: &UnixStream) -> io::Result<Option<(pid_t, c_int)>> {
+    let mut fd: c_int = -1;
+    let mut iov = iovec {
+        iov_base: ptr::from_mut(&mut fd).cast(),
+        iov_len: mem::size_of::<c_int>(),
+    };
+    // room for the credentials, aligned for `cmsghdr`
+    let mut control = [0u64; 8];
+
+    unsafe {
+        let mut message: msghdr = mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = mem::size_of_val(&control);
+
+        match check(recvmsg(judge.as_raw_fd(), &mut message, 0) as c_long)? {
+            0 => return Ok(None),
+            n if n as usize != mem::size_of::<c_int>() => {
+                return Err(Error::other("truncated seccomp listener"))
+            }
+            _ => {}
+        }
+
+        let mut header = CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == SOL_SOCKET && (*header).cmsg_type == SCM_CREDENTIALS {
+                let credentials: ucred = ptr::read_unaligned(CMSG_DATA(header).cast());
+                return Ok(Some((credentials.pid, fd)));
+            }
+            header = CMSG_NXTHDR(&message, header);
+        }
+    }
+
+    Err(Error::other("no credentials with the seccomp listener"))
+}
+
+fn check(result: c_long) -> io::Result<c_long> {
+    if result < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -0,0 +1,326 @@
+//! Points for a submission, computed from its report according to the contest's [`Scoring`].
+//!
+//! Each test earns a share of its points: all of them if it is accepted, the partial score given
+//! by the checker or interactor if it gets one, and none otherwise. A subtask's own points depend
+//! on the shares of its tests according to the [`ScoringModel`], and a task that is accepted
+//! outright earns the task score on top of everything else.
+//!
+//! Tasks with an answer have no tests, and only earn the answer score if the answer is accepted.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    contest::{Scoring, Task},
+    submit::{Report, TestReport, Verdict},
+};
+
+/// How the points for a subtask depend on its tests
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScoringModel {
+    /// The subtask's points are only awarded if every test is accepted (as in the IOI)
+    #[default]
+    AllOrNothing,
+    /// The subtask's points are split evenly between its tests
+    PerTest,
+    /// The subtask's points are scaled by the lowest share earned by any of its tests
+    MinRatio,
+}
+
+/// Points earned by a submission, laid out like its [`Report`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Score {
+    /// Points for the task, including its subtasks
+    pub task: f64,
+    /// Points for each subtask, including its tests
+    pub subtasks: Vec<f64>,
+    /// Points for each test
+    pub tests: Vec<Vec<f64>>,
+    /// Most points the task can earn
+    pub max: f64,
+}
+
+impl ScoringModel {
+    /// Share of a subtask's points earned, given the shares earned by its tests
+    fn share(&self, tests: &[f64]) -> f64 {
+        // a subtask without tests has nothing to earn its points with
+        if tests.is_empty() {
+            return 0.0;
+        }
+
+        match self {
+            ScoringModel::AllOrNothing => {
+                if tests.iter().all(|&share| share == 1.0) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ScoringModel::PerTest => tests.iter().sum::<f64>() / tests.len() as f64,
+            ScoringModel::MinRatio => tests.iter().copied().fold(1.0, f64::min),
+        }
+    }
+}
+
+impl Score {
+    pub fn new(scoring: &Scoring, task: &Task, report: &Report) -> Self {
+        if task.answer.is_some() {
+            let max = scoring.answer_score as f64;
+            return Score {
+                task: if report.task == Verdict::Accepted {
+                    max
+                } else {
+                    0.0
+                },
+                subtasks: Vec::new(),
+                tests: Vec::new(),
+                max,
+            };
+        }
+
+        let mut score = Score {
+            task: 0.0,
+            subtasks: Vec::with_capacity(report.subtasks.len()),
+            tests: Vec::with_capacity(report.tests.len()),
+            max: scoring.task_score as f64,
+        };
+
+        // a submission that doesn't compile earns nothing, whatever its tests were marked as
+        let compiled = report.task != Verdict::CompileError;
+
+        for (idx, subtask) in task.subtasks.iter().enumerate() {
+            let subtask_max = subtask.score.unwrap_or(scoring.subtask_score) as f64;
+            let test_max = scoring.test_score as f64;
+
+            let tests = report.tests.get(idx).map(Vec::as_slice).unwrap_or_default();
+            let shares: Vec<_> = tests
+                .iter()
+                .map(|test| if compiled { share(test) } else { 0.0 })
+                .collect();
+            let test_points: Vec<_> = shares.iter().map(|share| share * test_max).collect();
+            let subtask_points =
+                subtask_max * scoring.model.share(&shares) + test_points.iter().sum::<f64>();
+
+            score.task += subtask_points;
+            score.max += subtask_max + test_max * subtask.tests.len() as f64;
+            score.subtasks.push(subtask_points);
+            score.tests.push(test_points);
+        }
+
+        if report.task == Verdict::Accepted {
+            score.task += scoring.task_score as f64;
+        }
+
+        score
+    }
+}
+
+/// Share of a test's points earned
+fn share(test: &TestReport) -> f64 {
+    match test.verdict {
+        Verdict::Accepted => 1.0,
+        Verdict::PartialScore => test.score.unwrap_or_default(),
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contest::{Difficulty, Kind, Subtask, Test},
+        sandbox::ResourceUsage,
+    };
+
+    fn scoring(model: ScoringModel) -> Scoring {
+        Scoring {
+            answer_score: 100,
+            task_score: 20,
+            test_score: 1,
+            subtask_score: 10,
+            model,
+        }
+    }
+
+    /// Task with a subtask of `tests` tests for each element of `subtasks`
+    fn task(subtasks: &[usize], answer: Option<&str>) -> Task {
+        let test = Test {
+            input: String::new(),
+            output: String::new(),
+        };
+
+        Task {
+            name: "Task".to_owned(),
+            difficulty: Difficulty::Easy,
+            answer: answer.map(str::to_owned),
+            page: String::new(),
+            subtasks: subtasks
+                .iter()
+                .map(|&tests| Subtask {
+                    tests: vec![test.clone(); tests],
+                    score: None,
+                    resource_limits: None,
+                })
+                .collect(),
+            checker: None,
+            kind: Kind::Batch,
+            comparison: None,
+            resource_limits: None,
+        }
+    }
+
+    fn test(verdict: Verdict, score: Option<f64>) -> TestReport {
+        TestReport {
+            verdict,
+            resource_usage: ResourceUsage::default(),
+            score,
+            message: None,
+            mismatch: None,
+            transcript: None,
+            syscall: None,
+        }
+    }
+
+    fn report(task: Verdict, tests: Vec<Vec<TestReport>>) -> Report {
+        Report {
+            task,
+            subtasks: vec![task; tests.len()],
+            tests,
+        }
+    }
+
+    /// Two subtasks: one accepted, and one with an accepted, a half and a wrong test
+    fn partial() -> Report {
+        report(
+            Verdict::WrongAnswer,
+            vec![
+                vec![test(Verdict::Accepted, None), test(Verdict::Accepted, None)],
+                vec![
+                    test(Verdict::Accepted, None),
+                    test(Verdict::PartialScore, Some(0.5)),
+                    test(Verdict::WrongAnswer, None),
+                ],
+            ],
+        )
+    }
+
+    #[test]
+    fn all_or_nothing() {
+        let score = Score::new(
+            &scoring(ScoringModel::AllOrNothing),
+            &task(&[2, 3], None),
+            &partial(),
+        );
+        assert_eq!(score.tests, vec![vec![1.0, 1.0], vec![1.0, 0.5, 0.0]]);
+        assert_eq!(score.subtasks, vec![12.0, 1.5]);
+        assert_eq!(score.task, 13.5);
+        assert_eq!(score.max, 20.0 + 10.0 * 2.0 + 5.0);
+    }
+
+    #[test]
+    fn per_test() {
+        let score = Score::new(
+            &scoring(ScoringModel::PerTest),
+            &task(&[2, 3], None),
+            &partial(),
+        );
+        assert_eq!(score.subtasks, vec![12.0, 1.5 + 10.0 * 1.5 / 3.0]);
+        assert_eq!(score.task, 18.5);
+    }
+
+    #[test]
+    fn min_ratio() {
+        let mut report = partial();
+        report.tests[1].pop();
+
+        let score = Score::new(
+            &scoring(ScoringModel::MinRatio),
+            &task(&[2, 3], None),
+            &report,
+        );
+        assert_eq!(score.subtasks, vec![12.0, 1.5 + 5.0]);
+        assert_eq!(score.task, 18.5);
+    }
+
+    #[test]
+    fn task_bonus() {
+        let mut report = partial();
+        report.task = Verdict::Accepted;
+        report.tests[1] = vec![test(Verdict::Accepted, None); 3];
+
+        for model in [
+            ScoringModel::AllOrNothing,
+            ScoringModel::PerTest,
+            ScoringModel::MinRatio,
+        ] {
+            let score = Score::new(&scoring(model), &task(&[2, 3], None), &report);
+            assert_eq!(score.task, score.max, "{model:?}");
+            assert_eq!(score.task, 45.0, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn compile_error() {
+        let task = task(&[2, 3], None);
+        let report = report(
+            Verdict::CompileError,
+            vec![
+                vec![test(Verdict::CompileError, None); 2],
+                vec![test(Verdict::CompileError, None); 3],
+            ],
+        );
+
+        for model in [
+            ScoringModel::AllOrNothing,
+            ScoringModel::PerTest,
+            ScoringModel::MinRatio,
+        ] {
+            let score = Score::new(&scoring(model), &task, &report);
+            assert_eq!(score.task, 0.0, "{model:?}");
+            assert_eq!(score.subtasks, vec![0.0, 0.0], "{model:?}");
+            assert_eq!(score.max, 45.0, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn empty_subtasks() {
+        let task = task(&[0, 1], None);
+        let report = report(
+            Verdict::WrongAnswer,
+            vec![Vec::new(), vec![test(Verdict::Accepted, None)]],
+        );
+
+        for model in [
+            ScoringModel::AllOrNothing,
+            ScoringModel::PerTest,
+            ScoringModel::MinRatio,
+        ] {
+            assert_eq!(model.share(&[]), 0.0, "{model:?}");
+
+            let score = Score::new(&scoring(model), &task, &report);
+            assert_eq!(score.subtasks, vec![0.0, 11.0], "{model:?}");
+            assert_eq!(score.task, 11.0, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn answers() {
+        let task = task(&[], Some("42"));
+
+        let accepted = Score::new(
+            &scoring(ScoringModel::default()),
+            &task,
+            &report(Verdict::Accepted, Vec::new()),
+        );
+        assert_eq!((accepted.task, accepted.max), (100.0, 100.0));
+        assert!(accepted.subtasks.is_empty());
+
+        let wrong = Score::new(
+            &scoring(ScoringModel::default()),
+            &task,
+            &report(Verdict::WrongAnswer, Vec::new()),
+        );
+        assert_eq!((wrong.task, wrong.max), (0.0, 100.0));
+    }
+}
@@ -0,0 +1,580 @@
+//! Persistent storage for judging results.
+//!
+//! Each submission directory contains a `record.json` with the submission's details and final
+//! report, and an `events.jsonl` with every message sent to the client, one per line. A message's
+//! position in the event log (after the record's `first-event`) doubles as its SSE event ID.
+//!
+//! Updates that are only of interest while they're current (such as queue positions) aren't
+//! stored: clients following the submission live get the latest one, without an event ID.
+//!
+//! While a submission is judged again, its new messages go to `events.jsonl.tmp`, and its old
+//! record and event log are only replaced once judging finishes. If it fails, they're kept. The
+//! new messages are numbered on from the old ones, so that a client resuming from an old event ID
+//! starts the new event log from the beginning rather than part way through.
+
+use std::{
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ahash::AHashMap;
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{self, AsyncWriteExt},
+    sync::{mpsc, watch, Mutex},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::{
+    scoring::Score,
+    submit::{Message, Report},
+};
+
+const RECORD: &str = "record.json";
+const RECORD_TMP: &str = "record.json.tmp";
+const EVENTS: &str = "events.jsonl";
+const EVENTS_TMP: &str = "events.jsonl.tmp";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Record {
+    pub id: Uuid,
+    pub contest: String,
+    /// Task number (starting from 1)
+    pub task: usize,
+    /// User who made the submission (if given)
+    pub user: Option<String>,
+    /// Language of the code (`None` for answers)
+    pub language: Option<String>,
+    pub status: Status,
+    /// Final report (once judging is done)
+    pub report: Option<Report>,
+    /// Points earned (once judging is done)
+    pub score: Option<Score>,
+    /// Compiler warnings and errors
+    pub compile_stderr: Option<String>,
+    /// Reason for an internal judge error
+    pub error: Option<String>,
+    pub timings: Timings,
+    /// Event ID of the first message in the event log (which is only above 0 once the submission
+    /// has been judged again)
+    #[serde(default)]
+    pub first_event: usize,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Status {
+    Queued,
+    Judging,
+    Done,
+    Error,
+}
+
+/// Timestamps (milliseconds since the Unix epoch)
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Timings {
+    pub submitted: u64,
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+}
+
+impl Record {
+    pub fn new(
+        id: Uuid,
+        contest: String,
+        task: usize,
+        user: Option<String>,
+        language: Option<String>,
+    ) -> Self {
+        Record {
+            id,
+            contest,
+            task,
+            user,
+            language,
+            status: Status::Queued,
+            report: None,
+            score: None,
+            compile_stderr: None,
+            error: None,
+            timings: Timings {
+                submitted: now(),
+                started: None,
+                finished: None,
+            },
+            first_event: 0,
+        }
+    }
+}
+
+/// Submissions that are still being judged
+static LIVE: Lazy<std::sync::Mutex<AHashMap<Uuid, Arc<Entry>>>> = Lazy::new(Default::default);
+
+/// Stream of messages (serialized as JSON), together with their position in the event log (`None`
+/// for updates that aren't stored)
+pub type Events = ReceiverStream<(Option<usize>, String)>;
+
+/// Handle to the stored record of a submission that is being judged
+///
+/// Messages are also buffered in memory until judging finishes, so that clients can follow the
+/// event log live.
+#[derive(Debug)]
+pub struct Entry {
+    id: Uuid,
+    dir: PathBuf,
+    record: Mutex<Record>,
+    log: watch::Sender<Log>,
+    /// Event ID of the first message in the log
+    first_event: usize,
+    /// Whether the submission is being judged again, keeping its stored results until it's done
+    reopened: bool,
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    events: Vec<String>,
+    /// Latest update that isn't stored, until the next stored message
+    update: Option<String>,
+    finished: bool,
+}
+
+impl Entry {
+    fn new(record: Record, reopened: bool) -> Arc<Self> {
+        Arc::new(Entry {
+            id: record.id,
+            dir: dir(record.id),
+            first_event: record.first_event,
+            record: Mutex::new(record),
+            log: watch::Sender::new(Log::default()),
+            reopened,
+        })
+    }
+
+    pub async fn create(record: Record) -> io::Result<Arc<Self>> {
+        let entry = Entry::new(record, false);
+
+        entry.write(&*entry.record.lock().await).await?;
+        LIVE.lock().unwrap().insert(entry.id, entry.clone());
+        entry.push(&Message::Submitted { id: entry.id }).await;
+
+        Ok(entry)
+    }
+
+    /// Puts a stored submission back in the queue to judge it again, with a new event log that
+    /// replaces the stored one (along with the stored results) once judging is done
+    ///
+    /// Returns `None` if the submission is still being judged.
+    pub async fn reopen(record: Record) -> io::Result<Option<Arc<Self>>> {
+        let old_events = match fs::read_to_string(dir(record.id).join(EVENTS)).await {
+            Ok(events) => events.lines().count(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        let record = Record {
+            status: Status::Queued,
+            report: None,
+            score: None,
+            compile_stderr: None,
+            error: None,
+            timings: Timings {
+                started: None,
+                finished: None,
+                ..record.timings
+            },
+            first_event: record.first_event + old_events,
+            ..record
+        };
+        let entry = Entry::new(record, true);
+
+        {
+            let mut live = LIVE.lock().unwrap();
+            if live.contains_key(&entry.id) {
+                return Ok(None);
+            }
+            live.insert(entry.id, entry.clone());
+        }
+
+        if let Err(e) = fs::write(entry.dir.join(EVENTS_TMP), "").await {
+            LIVE.lock().unwrap().remove(&entry.id);
+            return Err(e);
+        }
+        entry.push(&Message::Submitted { id: entry.id }).await;
+
+        Ok(Some(entry))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Marks the start of judging (after waiting in the queue)
+    pub async fn start(&self) -> io::Result<()> {
+        let mut record = self.record.lock().await;
+        record.status = Status::Judging;
+        record.timings.started = Some(now());
+        self.save(&record).await
+    }
+
+    /// Puts the submission back in the queue (after its worker died), telling clients to throw
+    /// away the messages from the attempt that was cut short
+    pub async fn requeue(&self) -> io::Result<()> {
+        {
+            let mut record = self.record.lock().await;
+            record.status = Status::Queued;
+            record.compile_stderr = None;
+            record.timings.started = None;
+            self.save(&record).await?;
+        }
+
+        self.push(&Message::Requeued).await;
+        Ok(())
+    }
+
+    /// Appends a message to the event log, updating the record if necessary
+    pub async fn push(&self, message: &Message) {
+        let json = serde_json::to_string(message).unwrap();
+        let mut record = self.record.lock().await;
+
+        if let Err(e) = self.append(&json).await {
+            tracing::error!("failed to store message: {e}");
+        }
+
+        let updated = match message {
+            Message::Compiled { stderr, .. } => {
+                record.compile_stderr = Some(stderr.clone());
+                true
+            }
+            Message::Done { report, score } => {
+                record.status = Status::Done;
+                record.report = Some(report.clone());
+                record.score = Some(score.clone());
+                record.timings.finished = Some(now());
+                true
+            }
+            Message::Error { reason } => {
+                record.status = Status::Error;
+                record.error = Some(reason.clone());
+                record.timings.finished = Some(now());
+                true
+            }
+            _ => false,
+        };
+
+        if updated {
+            let result = match message {
+                Message::Done { .. } if self.reopened => self.replace(&record).await,
+                Message::Error { .. } if self.reopened => self.discard().await,
+                _ => self.save(&record).await,
+            };
+            if let Err(e) = result {
+                tracing::error!("failed to store record: {e}");
+            }
+        }
+
+        let finished = matches!(message, Message::Done { .. } | Message::Error { .. });
+
+        self.log.send_modify(|log| {
+            log.events.push(json);
+            log.update = None;
+            log.finished = finished;
+        });
+
+        if finished {
+            LIVE.lock().unwrap().remove(&self.id);
+        }
+    }
+
+    /// Sends a message to the clients following the submission live without storing it, replacing
+    /// any such message sent since the last stored one
+    pub fn update(&self, message: &Message) {
+        let json = serde_json::to_string(message).unwrap();
+        self.log.send_modify(|log| log.update = Some(json));
+    }
+
+    /// Follows the event log, starting from the message with event ID `from` (or the first one, if
+    /// that's from an earlier event log)
+    pub fn subscribe(&self, from: usize) -> Events {
+        let (tx, rx) = mpsc::channel(64);
+        let mut log = self.log.subscribe();
+        let first_event = self.first_event;
+
+        tokio::spawn(async move {
+            let mut next = from.saturating_sub(first_event);
+            let mut sent_update = None;
+
+            loop {
+                let (events, update, finished) = {
+                    let log = log.borrow_and_update();
+                    let events = log.events.get(next..).unwrap_or_default().to_vec();
+                    (events, log.update.clone(), log.finished)
+                };
+
+                for json in events {
+                    if tx.send((Some(first_event + next), json)).await.is_err() {
+                        return;
+                    }
+                    next += 1;
+                }
+
+                if update != sent_update {
+                    if let Some(json) = &update {
+                        if tx.send((None, json.clone())).await.is_err() {
+                            return;
+                        }
+                    }
+                    sent_update = update;
+                }
+
+                if finished || log.changed().await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    async fn append(&self, json: &str) -> io::Result<()> {
+        let name = if self.reopened { EVENTS_TMP } else { EVENTS };
+        let mut events = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(name))
+            .await?;
+        events.write_all(format!("{json}\n").as_bytes()).await
+    }
+
+    /// Stores the record, unless the submission is being judged again
+    async fn save(&self, record: &Record) -> io::Result<()> {
+        if self.reopened {
+            return Ok(());
+        }
+        self.write(record).await
+    }
+
+    /// Replaces the stored results of a submission that has been judged again
+    async fn replace(&self, record: &Record) -> io::Result<()> {
+        self.write(record).await?;
+        fs::rename(self.dir.join(EVENTS_TMP), self.dir.join(EVENTS)).await
+    }
+
+    /// Throws away the event log of a submission that failed to be judged again, keeping its
+    /// stored results
+    async fn discard(&self) -> io::Result<()> {
+        tracing::warn!(
+            "failed to judge submission {} again, keeping its old results",
+            self.id
+        );
+        fs::remove_file(self.dir.join(EVENTS_TMP)).await
+    }
+
+    async fn write(&self, record: &Record) -> io::Result<()> {
+        // write to a temporary file first, so readers never see a partially written record
+        let tmp = self.dir.join(RECORD_TMP);
+        fs::write(&tmp, serde_json::to_vec(record)?).await?;
+        fs::rename(tmp, self.dir.join(RECORD)).await
+    }
+}
+
+/// Directory containing the code and stored results of a submission
+pub fn dir(id: Uuid) -> PathBuf {
+    Path::new("submissions").join(id.to_string())
+}
+
+/// Whether a file in a submission directory belongs to the store, rather than the submission
+pub fn is_store_file(name: &OsStr) -> bool {
+    matches!(
+        name.to_str(),
+        Some(RECORD | RECORD_TMP | EVENTS | EVENTS_TMP)
+    )
+}
+
+/// Reads the stored record of a submission, if it exists
+pub async fn record(id: Uuid) -> io::Result<Option<Record>> {
+    match fs::read(dir(id).join(RECORD)).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the stored records of every submission
+pub async fn records() -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut read_dir = fs::read_dir("submissions").await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        match fs::read(entry.path().join(RECORD)).await {
+            Ok(bytes) => records.push(serde_json::from_slice(&bytes)?),
+            // e.g. a submission whose record hasn't been written yet
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Replays the messages of a submission starting from event ID `from`, following the event log
+/// if the submission is still being judged
+pub async fn events(id: Uuid, from: usize) -> io::Result<Option<Events>> {
+    let live = LIVE.lock().unwrap().get(&id).cloned();
+    if let Some(entry) = live {
+        return Ok(Some(entry.subscribe(from)));
+    }
+
+    let Some(record) = record(id).await? else {
+        return Ok(None);
+    };
+    let dir = dir(id);
+
+    let events = match fs::read_to_string(dir.join(EVENTS)).await {
+        Ok(events) => events,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let events: Vec<_> = events
+        .lines()
+        .enumerate()
+        .skip(from.saturating_sub(record.first_event))
+        .map(|(idx, json)| (Some(record.first_event + idx), json.to_owned()))
+        .collect();
+
+    let (tx, rx) = mpsc::channel(events.len().max(1));
+    for event in events {
+        tx.try_send(event).expect("channel has enough capacity");
+    }
+
+    Ok(Some(ReceiverStream::new(rx)))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the Unix epoch")
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::submit::Verdict;
+
+    /// Submission directory that is removed once the test finishes
+    struct TempSubmission(Uuid);
+
+    impl TempSubmission {
+        async fn new() -> Self {
+            let id = Uuid::new_v4();
+            fs::create_dir_all(dir(id)).await.unwrap();
+            TempSubmission(id)
+        }
+
+        fn record(&self) -> Record {
+            Record::new(self.0, "contest".to_owned(), 1, None, Some("C".to_owned()))
+        }
+    }
+
+    impl Drop for TempSubmission {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(dir(self.0));
+            // only removed once the last test is done with it
+            let _ = std::fs::remove_dir("submissions");
+        }
+    }
+
+    async fn collect(events: Events) -> Vec<(Option<usize>, Message)> {
+        events
+            .map(|(id, json)| (id, serde_json::from_str(&json).unwrap()))
+            .collect()
+            .await
+    }
+
+    fn error() -> Message {
+        Message::Error {
+            reason: "test".to_owned(),
+        }
+    }
+
+    fn done(verdict: Verdict) -> Message {
+        Message::Done {
+            report: Report {
+                task: verdict,
+                subtasks: Vec::new(),
+                tests: Vec::new(),
+            },
+            score: Score {
+                task: 0.0,
+                subtasks: Vec::new(),
+                tests: Vec::new(),
+                max: 0.0,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn requeue_resets_judging() {
+        let submission = TempSubmission::new().await;
+        let entry = Entry::create(submission.record()).await.unwrap();
+        entry.start().await.unwrap();
+        entry.push(&Message::Compiling).await;
+
+        entry.requeue().await.unwrap();
+        let stored = record(submission.0).await.unwrap().unwrap();
+        assert_eq!(stored.status, Status::Queued);
+        assert_eq!(stored.timings.started, None);
+
+        entry.start().await.unwrap();
+        entry.push(&Message::Compiling).await;
+        entry.push(&error()).await;
+
+        let events = events(submission.0, 0).await.unwrap().unwrap();
+        assert_eq!(
+            collect(events).await,
+            [
+                (Some(0), Message::Submitted { id: submission.0 }),
+                (Some(1), Message::Compiling),
+                (Some(2), Message::Requeued),
+                (Some(3), Message::Compiling),
+                (Some(4), error()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reopened_event_ids_carry_on() {
+        let submission = TempSubmission::new().await;
+        let entry = Entry::create(submission.record()).await.unwrap();
+        entry.push(&done(Verdict::WrongAnswer)).await;
+
+        let old = record(submission.0).await.unwrap().unwrap();
+        let entry = Entry::reopen(old).await.unwrap().unwrap();
+        // e.g. a client that followed the old event log to the end
+        let live = entry.subscribe(2);
+        entry.push(&done(Verdict::Accepted)).await;
+
+        let new = [
+            (Some(2), Message::Submitted { id: submission.0 }),
+            (Some(3), done(Verdict::Accepted)),
+        ];
+        assert_eq!(collect(live).await, new);
+
+        let stored = record(submission.0).await.unwrap().unwrap();
+        assert_eq!(stored.first_event, 2);
+        let replay =
+            |from| async move { collect(events(submission.0, from).await.unwrap().unwrap()).await };
+        assert_eq!(replay(1).await, new);
+        assert_eq!(replay(3).await, new[1..]);
+    }
+}
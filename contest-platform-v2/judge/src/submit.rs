@@ -1,127 +1,295 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use axum::response::sse::Event;
-use color_eyre::eyre::WrapErr;
+use color_eyre::eyre::{ensure, eyre, WrapErr};
 use schemars::JsonSchema;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::{watch, Semaphore},
-    task::JoinSet,
+    fs,
+    sync::{mpsc, watch},
+    task::{JoinHandle, JoinSet},
 };
+use uuid::Uuid;
 use yansi::Paint;
 
 use crate::{
-    contest::{Config, Language, Task, Test},
-    sandbox::{run, Output, Profile, ResourceUsage},
+    cache::{self, CompilerOutput, Snapshot},
+    checker,
+    compare::{Comparison, Mismatch},
+    contest::{Config, Kind, Language, Program, Task, Test},
+    interactor,
+    registry::Loaded,
+    sandbox::{run, CompileLimit, Exchange, Limit, Output, Profile, ResourceLimits, ResourceUsage},
+    scoring::Score,
+    store::Entry,
 };
 
-const MAX_CONCURRENT_SUBMISSIONS: usize = 5;
-static RATE_LIMIT: Semaphore = Semaphore::const_new(MAX_CONCURRENT_SUBMISSIONS);
+/// Name of the file that an answer is saved to in its submission directory
+pub const ANSWER_FILENAME: &str = "answer.txt";
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Report {
-    task: Verdict,
-    subtasks: Vec<Verdict>,
-    tests: Vec<Vec<TestReport>>,
+    pub task: Verdict,
+    pub subtasks: Vec<Verdict>,
+    pub tests: Vec<Vec<TestReport>>,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct TestReport {
-    verdict: Verdict,
-    resource_usage: ResourceUsage,
+    pub verdict: Verdict,
+    pub resource_usage: ResourceUsage,
+    /// Partial score between 0 and 1 (custom checkers only)
+    pub score: Option<f64>,
+    /// Message from the custom checker or interactor
+    pub message: Option<String>,
+    /// Location of the first difference from the expected output
+    pub mismatch: Option<Mismatch>,
+    /// Interaction between the submission and the interactor (interactive tasks only)
+    pub transcript: Option<Vec<Exchange>>,
+    /// Syscall that the submission was stopped for making (restricted function verdicts only)
+    pub syscall: Option<String>,
+}
+
+impl TestReport {
+    fn new(verdict: Verdict, resource_usage: ResourceUsage) -> Self {
+        TestReport {
+            verdict,
+            resource_usage,
+            score: None,
+            message: None,
+            mismatch: None,
+            transcript: None,
+            syscall: None,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+#[derive(
+    Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
 pub enum Verdict {
     CompileError,
+    RestrictedFunction,
     RuntimeError,
     WrongAnswer,
     TimeLimitExceeded,
     MemoryLimitExceeded,
+    OutputLimitExceeded,
+    IdlenessLimitExceeded,
     Skipped,
+    PartialScore,
     Accepted,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum Message {
-    /// Queued for submission
-    Queued { tests: u32 },
+    /// The submission was accepted, with the ID to look it up by (first)
+    Submitted { id: Uuid },
+    /// Waiting in the queue (sent again whenever the position changes)
+    Queued {
+        tests: u32,
+        /// Position in the queue (starting from 1)
+        position: u32,
+    },
+    /// Back in the queue after its worker was lost, so any messages since it last left the queue
+    /// no longer apply (followed by its new position)
+    Requeued,
     /// Indicates that the compile step has been started (optional)
     Compiling,
     /// Provides compiler warnings and errors (optional)
-    Compiled { exit_code: i32, stderr: String },
+    Compiled {
+        exit_code: i32,
+        stderr: String,
+        /// The compile limit that stopped the compiler, if any
+        exceeded: Option<CompileLimit>,
+    },
     /// Judging status
     Judging { verdict: Verdict },
     /// Tests were skipped due to exceeding resource usage
     Skipping { estimated_count: u32 },
     /// Judging completed successfully (final)
-    Done { report: Report },
+    Done { report: Report, score: Score },
     /// The judge experienced an internal error (final)
     Error { reason: String },
 }
 
-type Sender = tokio::sync::mpsc::Sender<Result<Event, std::convert::Infallible>>;
+/// A submission to be judged, with everything that a worker needs to know about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    pub contest: String,
+    /// Task number (starting from 1)
+    pub task: usize,
+    /// Name of the language, which must be one of the contest's languages
+    pub language: String,
+    pub code: String,
+    /// Version of the contest to judge the submission with (see [`Loaded::version`])
+    pub version: String,
+}
 
 #[derive(Clone)]
 struct State {
-    tx: Sender,
+    /// Directory the submission is compiled and run in
     dir: Arc<Path>,
-    config: &'static Config,
-    task: &'static Task,
-    language: &'static Language,
+    messages: mpsc::Sender<Message>,
+    programs: Arc<Path>,
+    /// The version of the contest that was current when the submission was made
+    contest: Arc<Loaded>,
+    task: usize,
+    language: usize,
 }
 
 impl State {
+    fn new(
+        job: &Job,
+        contest: Arc<Loaded>,
+        dir: &Path,
+        messages: mpsc::Sender<Message>,
+    ) -> color_eyre::Result<Self> {
+        ensure!(
+            (1..=contest.tasks.len()).contains(&job.task),
+            "task #{} for contest {} not found",
+            job.task,
+            job.contest
+        );
+        let language = contest
+            .config
+            .languages
+            .iter()
+            .position(|lang| lang.name == job.language)
+            .ok_or_else(|| eyre!("unsupported language: {}", job.language))?;
+
+        Ok(State {
+            dir: Arc::from(dir),
+            messages,
+            programs: Arc::from(contest.programs(job.task)),
+            contest,
+            task: job.task - 1,
+            language,
+        })
+    }
+
+    fn config(&self) -> &Config {
+        &self.contest.config
+    }
+
+    fn task(&self) -> &Task {
+        &self.contest.tasks[self.task]
+    }
+
+    fn language(&self) -> &Language {
+        &self.contest.config.languages[self.language]
+    }
+
     async fn run(&self, profile: Profile, stdin: &[u8]) -> tokio::io::Result<Output> {
         let command = match profile {
-            Profile::Compile => self
-                .language
+            Profile::Compile(_) => self
+                .language()
                 .compile
                 .as_ref()
                 .expect("attempted to execute non-existent compile command"),
-            Profile::Run(_) => &self.language.run,
+            Profile::Run(..) => &self.language().run,
         };
 
         run(&self.dir, command, stdin, profile).await
     }
 
     async fn send(&self, message: Message) {
-        if let Err(e) = self
-            .tx
-            .send(Ok(Event::default().json_data(message).unwrap()))
-            .await
-        {
-            tracing::error!("failed to send message: {e}");
-        }
+        // the receiver only goes away if nobody is waiting for the result anymore
+        self.messages.send(message).await.ok();
+    }
+
+    /// Scores the final report and sends it
+    async fn done(&self, report: Report) {
+        let score = Score::new(&self.contest.scoring, self.task(), &report);
+        self.send(Message::Done { report, score }).await;
     }
 }
 
+/// Judges an answer to a task (numbered from 1) that has one, which involves no compiling or
+/// running, so it skips the queue
 #[tracing::instrument(skip_all)]
-pub async fn submit(
-    tx: Sender,
-    dir: impl AsRef<Path>,
-    config: &'static Config,
-    task: &'static Task,
-    language: &'static Language,
-) {
-    let state = State {
-        tx,
-        dir: Arc::from(dir.as_ref()),
-        config,
-        task,
-        language,
-    };
-
-    state
-        .send(Message::Queued {
-            tests: task.subtasks.iter().map(|s| s.tests.len() as u32).sum(),
+pub async fn submit_answer(entry: Arc<Entry>, contest: Arc<Loaded>, task: usize, answer: String) {
+    let task = &contest.tasks[task - 1];
+    let expected = task.answer.as_deref().expect("task has no answer");
+
+    entry
+        .push(&Message::Queued {
+            tests: 0,
+            position: 1,
         })
         .await;
+    if let Err(e) = entry.start().await {
+        tracing::error!("failed to store submission status: {e}");
+    }
 
-    let _permit = RATE_LIMIT.acquire().await.expect("semaphore closed");
+    let verdict = judge_answer(task.comparison(&contest.config), expected, &answer);
+    let report = Report {
+        task: verdict,
+        subtasks: Vec::new(),
+        tests: Vec::new(),
+    };
 
-    if let Err(report) = submit_inner(state.clone()).await {
+    let score = Score::new(&contest.scoring, task, &report);
+    entry.push(&Message::Done { report, score }).await;
+}
+
+fn judge_answer(comparison: Comparison, expected: &str, answer: &str) -> Verdict {
+    // the mismatch isn't reported, as it would give away the expected answer
+    match comparison.compare(expected, answer) {
+        Ok(()) => Verdict::Accepted,
+        Err(_) => Verdict::WrongAnswer,
+    }
+}
+
+/// Judges a submission in `dir` in the background, returning every message about it after it
+/// leaves the queue (up to and including the final one), along with the task doing the judging
+///
+/// Dropping the receiver cancels the judging (stopping its runs), after which the task finishes.
+pub fn spawn(
+    job: Job,
+    contest: Arc<Loaded>,
+    dir: PathBuf,
+) -> (mpsc::Receiver<Message>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel(16);
+
+    let task = tokio::spawn(async move {
+        let mut judging = tokio::spawn(submit(job, contest, dir, tx.clone()));
+
+        tokio::select! {
+            result = &mut judging => {
+                // otherwise a bug in the judge would leave the submission hanging
+                if let Err(e) = result {
+                    let reason = format!("judging crashed: {e}");
+                    tx.send(Message::Error { reason }).await.ok();
+                }
+            }
+            _ = tx.closed() => {
+                tracing::info!("nobody is waiting for the messages, cancelling judging");
+                judging.abort();
+                judging.await.ok();
+            }
+        }
+    });
+
+    (rx, task)
+}
+
+#[tracing::instrument(skip_all, fields(id = %job.id))]
+async fn submit(job: Job, contest: Arc<Loaded>, dir: PathBuf, messages: mpsc::Sender<Message>) {
+    let result = async {
+        let state = State::new(&job, contest, &dir, messages.clone())?;
+        fs::write(dir.join(&state.language().filename), &job.code)
+            .await
+            .wrap_err("failed to write code")?;
+        let key = cache::Key::new(state.language(), &job.code);
+        submit_inner(state, key).await
+    };
+
+    if let Err(report) = result.await {
         tracing::error!("{report:?}");
 
         let mut reason = String::new();
@@ -129,36 +297,33 @@ pub async fn submit(
             reason.push_str(&format!("{i}: {e}\n"));
         }
 
-        state.send(Message::Error { reason }).await;
+        messages.send(Message::Error { reason }).await.ok();
     }
 }
 
-async fn submit_inner(state: State) -> color_eyre::Result<()> {
-    if state.language.compile.is_some() {
-        if !compile(state.clone())
+async fn submit_inner(state: State, key: cache::Key) -> color_eyre::Result<()> {
+    if state.language().compile.is_some() {
+        if !compile(state.clone(), key)
             .await
             .wrap_err("failed to compile submission")?
         {
             let report = Report {
                 task: Verdict::CompileError,
-                subtasks: vec![Verdict::CompileError; state.task.subtasks.len()],
+                subtasks: vec![Verdict::CompileError; state.task().subtasks.len()],
                 tests: state
-                    .task
+                    .task()
                     .subtasks
                     .iter()
                     .map(|s| {
                         vec![
-                            TestReport {
-                                verdict: Verdict::CompileError,
-                                resource_usage: ResourceUsage::default()
-                            };
+                            TestReport::new(Verdict::CompileError, ResourceUsage::default());
                             s.tests.len()
                         ]
                     })
                     .collect(),
             };
 
-            state.send(Message::Done { report }).await;
+            state.done(report).await;
             return Ok(());
         }
     } else {
@@ -169,43 +334,88 @@ async fn submit_inner(state: State) -> color_eyre::Result<()> {
         .await
         .wrap_err("failed to judge submission")?;
 
-    state.send(Message::Done { report }).await;
+    state.done(report).await;
     Ok(())
 }
 
 #[tracing::instrument(skip(state))]
-async fn compile(state: State) -> color_eyre::Result<bool> {
+async fn compile(state: State, key: cache::Key) -> color_eyre::Result<bool> {
+    if let Some(CompilerOutput { exit_code, stderr }) = cache::restore(&key, &state.dir).await {
+        tracing::trace!("reusing cached compile (exit code: {exit_code})");
+        if exit_code != 0 || !stderr.is_empty() {
+            state
+                .send(Message::Compiled {
+                    exit_code,
+                    stderr,
+                    exceeded: None,
+                })
+                .await;
+        }
+        return Ok(exit_code == 0);
+    }
+
     state.send(Message::Compiling).await;
 
+    // the files that compiling produces are found by comparing with the files from before it
+    let before = Snapshot::take(&state.dir)
+        .await
+        .wrap_err("failed to list submission directory")?;
+
+    let compile_limits = state.language().compile_limits(state.config());
     let output = state
-        .run(Profile::Compile, &[])
+        .run(Profile::Compile(compile_limits), &[])
         .await
         .wrap_err("failed to execute compile command")?;
     let status = output.exit_status();
     let exit_code = status.code().unwrap_or(-1);
+    let stderr = output.stderr_utf8().unwrap_or_default().to_owned();
 
     if status.success() {
-        if !output.stderr().is_empty() {
+        if !stderr.is_empty() {
             tracing::warn!("compiler warnings emitted");
             state
                 .send(Message::Compiled {
                     exit_code,
-                    stderr: output.stderr_utf8().unwrap_or_default().to_owned(),
+                    stderr: stderr.clone(),
+                    exceeded: None,
                 })
                 .await;
         }
 
+        cache::store(
+            &key,
+            &state.dir,
+            &before,
+            CompilerOutput { exit_code, stderr },
+        )
+        .await;
+
         tracing::trace!("compile succeeded");
         Ok(true)
     } else {
+        let exceeded = compile_limits.exceeded(status, output.resource_usage());
         state
             .send(Message::Compiled {
                 exit_code,
-                stderr: output.stderr_utf8().unwrap_or_default().to_owned(),
+                stderr: stderr.clone(),
+                exceeded,
             })
             .await;
 
-        if exit_code != -1 {
+        // a compiler stopped by a limit (or a signal) might succeed another time
+        if exceeded.is_none() && exit_code != -1 {
+            cache::store(
+                &key,
+                &state.dir,
+                &before,
+                CompilerOutput { exit_code, stderr },
+            )
+            .await;
+        }
+
+        if let Some(limit) = exceeded {
+            tracing::error!("compilation failed (exceeded {limit:?} limit)");
+        } else if exit_code != -1 {
             tracing::error!("compilation failed (exit code: {exit_code})");
         } else {
             tracing::error!("compilation failed (terminated by signal)");
@@ -219,16 +429,18 @@ async fn compile(state: State) -> color_eyre::Result<bool> {
 async fn judge(state: State) -> color_eyre::Result<Report> {
     let mut subtask_set = JoinSet::new();
 
-    for (subtask_idx, subtask) in state.task.subtasks.iter().enumerate() {
+    for subtask_idx in 0..state.task().subtasks.len() {
         let state = state.clone();
         subtask_set.spawn(async move {
+            let subtask = &state.task().subtasks[subtask_idx];
             let (skip_tx, skip_rx) = watch::channel(0u8);
             let mut test_set = JoinSet::new();
 
-            for (test_idx, test) in subtask.tests.iter().enumerate() {
+            for test_idx in 0..subtask.tests.len() {
                 let (state, skip_tx) = (state.clone(), skip_tx.clone());
                 test_set.spawn(async move {
-                    let test_report = run_test(state.clone(), skip_tx, test)
+                    let test = &state.task().subtasks[subtask_idx].tests[test_idx];
+                    let test_report = run_test(state.clone(), skip_tx, subtask_idx, test)
                         .await
                         .wrap_err("failed to run test")?;
 
@@ -238,11 +450,17 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
                         test_idx + 1,
                         match test_report.verdict {
                             Verdict::CompileError => Paint::yellow("Compile Error"),
+                            Verdict::RestrictedFunction => Paint::yellow("Restricted Function"),
                             Verdict::RuntimeError => Paint::yellow("Runtime Error"),
                             Verdict::MemoryLimitExceeded => Paint::magenta("Memory Limit Exceeded"),
                             Verdict::TimeLimitExceeded => Paint::magenta("Time Limit Exceeded"),
+                            Verdict::OutputLimitExceeded => Paint::magenta("Output Limit Exceeded"),
+                            Verdict::IdlenessLimitExceeded => {
+                                Paint::magenta("Idleness Limit Exceeded")
+                            }
                             Verdict::WrongAnswer => Paint::red("Wrong Answer"),
                             Verdict::Skipped => Paint::blue("Skipped"),
+                            Verdict::PartialScore => Paint::blue("Partial Score"),
                             Verdict::Accepted => Paint::green("Accepted"),
                         }
                         .bold()
@@ -259,26 +477,24 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
             }
 
             let mut subtask_verdict = Verdict::Accepted;
-            let mut subtask_reports = vec![
-                TestReport {
-                    verdict: Verdict::Skipped,
-                    resource_usage: ResourceUsage::default()
-                };
-                subtask.tests.len()
-            ];
+            let mut subtask_reports =
+                vec![
+                    TestReport::new(Verdict::Skipped, ResourceUsage::default());
+                    subtask.tests.len()
+                ];
 
             while let Some(result) = test_set.join_next().await {
                 let (test_idx, test_report) = result??;
                 subtask_verdict = subtask_verdict.min(test_report.verdict);
                 subtask_reports[test_idx] = test_report;
 
-                if *skip_rx.borrow() > state.config.skip_count {
+                if *skip_rx.borrow() > state.config().skip_count {
                     tracing::warn!("exceeded skip count for subtask, skipping");
                     test_set.abort_all();
                     state
                         .send(Message::Skipping {
                             estimated_count: (subtask.tests.len()
-                                - state.config.skip_count as usize)
+                                - state.config().skip_count as usize)
                                 as u32,
                         })
                         .await;
@@ -292,8 +508,8 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
 
     let mut report = Report {
         task: Verdict::Accepted,
-        subtasks: vec![Verdict::Accepted; state.task.subtasks.len()],
-        tests: vec![vec![]; state.task.subtasks.len()],
+        subtasks: vec![Verdict::Accepted; state.task().subtasks.len()],
+        tests: vec![vec![]; state.task().subtasks.len()],
     };
 
     while let Some(result) = subtask_set.join_next().await {
@@ -309,37 +525,192 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
 async fn run_test(
     state: State,
     skip_tx: watch::Sender<u8>,
+    subtask_idx: usize,
     test: &Test,
 ) -> color_eyre::Result<TestReport> {
-    let output = state
-        .run(
-            Profile::Run(state.config.resource_limits),
-            test.input.as_bytes(),
+    // the checker and interactor aren't written in the submission's language, so their limits
+    // aren't scaled
+    let checker_limits = state.task().resource_limits(state.config(), subtask_idx);
+    let resource_limits = state.language().scale(checker_limits);
+
+    if let Kind::Interactive { interactor } = &state.task().kind {
+        return run_interactive_test(
+            state.clone(),
+            skip_tx,
+            resource_limits,
+            checker_limits,
+            test,
+            interactor,
         )
+        .await;
+    }
+
+    let filter = state.language().syscall_filter(state.config())?;
+    let output = state
+        .run(Profile::Run(resource_limits, filter), test.input.as_bytes())
         .await?;
 
-    let status = output.exit_status();
-    let resource_usage = output.resource_usage();
-
-    let verdict =
-        if resource_usage.exceeded(state.config.resource_limits) && status.code().is_none() {
-            skip_tx.send_modify(|count| *count += 1);
-            if resource_usage.exceeded_time(state.config.resource_limits) {
-                Verdict::TimeLimitExceeded
-            } else {
-                Verdict::MemoryLimitExceeded
+    let mut test_report = TestReport::new(Verdict::Accepted, output.resource_usage());
+
+    test_report.verdict = if let Some(syscall) = output.denied_syscall() {
+        test_report.syscall = Some(syscall.to_owned());
+        Verdict::RestrictedFunction
+    } else if let Some(verdict) = exceeded_limits(resource_limits, &output) {
+        skip_tx.send_modify(|count| *count += 1);
+        verdict
+    } else if output.exit_status().success() {
+        match &state.task().checker {
+            Some(checker) => {
+                let outcome = checker::check(
+                    &state.programs.join("checker"),
+                    state.config(),
+                    checker,
+                    checker_limits,
+                    test,
+                    output.stdout(),
+                )
+                .await
+                .wrap_err("failed to run checker")?;
+
+                test_report.score = outcome.score;
+                test_report.message = outcome.message;
+                outcome.verdict
             }
-        } else if status.success() {
-            match output.stdout_utf8() {
-                Ok(stdout) if stdout.trim() == test.output.trim() => Verdict::Accepted,
-                _ => Verdict::WrongAnswer,
+            None => {
+                let stdout = String::from_utf8_lossy(output.stdout());
+                match state
+                    .task()
+                    .comparison(state.config())
+                    .compare(&test.output, &stdout)
+                {
+                    Ok(()) => Verdict::Accepted,
+                    Err(mismatch) => {
+                        test_report.mismatch = Some(mismatch);
+                        Verdict::WrongAnswer
+                    }
+                }
             }
-        } else {
+        }
+    } else {
+        Verdict::RuntimeError
+    };
+
+    Ok(test_report)
+}
+
+async fn run_interactive_test(
+    state: State,
+    skip_tx: watch::Sender<u8>,
+    resource_limits: ResourceLimits,
+    interactor_limits: ResourceLimits,
+    test: &Test,
+    interactor: &Program,
+) -> color_eyre::Result<TestReport> {
+    let interaction = interactor::interact(
+        &state.dir,
+        state.language(),
+        &state.programs.join("interactor"),
+        state.config(),
+        interactor,
+        resource_limits,
+        interactor_limits,
+        test,
+    )
+    .await?;
+
+    let mut test_report =
+        TestReport::new(Verdict::Accepted, interaction.contestant.resource_usage());
+    test_report.transcript = Some(interaction.transcript);
+
+    test_report.verdict = if let Some(syscall) = interaction.contestant.denied_syscall() {
+        test_report.syscall = Some(syscall.to_owned());
+        Verdict::RestrictedFunction
+    } else if let Some(verdict) = exceeded_limits(resource_limits, &interaction.contestant) {
+        skip_tx.send_modify(|count| *count += 1);
+        verdict
+    } else {
+        let outcome = interactor::outcome(&interaction.interactor, interactor_limits)?;
+
+        // the interactor may exit early on a wrong answer, which can make the submission
+        // crash on a closed pipe
+        if outcome.verdict != Verdict::WrongAnswer
+            && !interaction.contestant.exit_status().success()
+        {
             Verdict::RuntimeError
-        };
+        } else {
+            test_report.score = outcome.score;
+            test_report.message = outcome.message;
+            outcome.verdict
+        }
+    };
+
+    Ok(test_report)
+}
 
-    Ok(TestReport {
-        verdict,
-        resource_usage,
+fn exceeded_limits(resource_limits: ResourceLimits, output: &Output) -> Option<Verdict> {
+    let limit = resource_limits.exceeded(output.exit_status(), output.resource_usage())?;
+    Some(match limit {
+        Limit::Time => Verdict::TimeLimitExceeded,
+        Limit::Memory => Verdict::MemoryLimitExceeded,
+        Limit::Output => Verdict::OutputLimitExceeded,
+        Limit::WallTime => Verdict::IdlenessLimitExceeded,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepted(comparison: Comparison, expected: &str, answer: &str) -> bool {
+        judge_answer(comparison, expected, answer) == Verdict::Accepted
+    }
+
+    #[test]
+    fn trims_answers() {
+        assert!(accepted(Comparison::Exact, "42", "  42\n"));
+        assert!(accepted(
+            Comparison::Exact,
+            "Pegasus Phoenix",
+            "Pegasus Phoenix\r\n"
+        ));
+        assert!(!accepted(
+            Comparison::Exact,
+            "Pegasus Phoenix",
+            "Pegasus  Phoenix"
+        ));
+        assert!(accepted(
+            Comparison::Tokens,
+            "Pegasus Phoenix",
+            "Pegasus\n Phoenix"
+        ));
+        assert!(!accepted(Comparison::Exact, "42", "4 2"));
+        assert!(!accepted(Comparison::Exact, "42", ""));
+    }
+
+    #[test]
+    fn ignores_case() {
+        assert!(!accepted(Comparison::Exact, "Phoenix", "phoenix"));
+        assert!(!accepted(Comparison::Tokens, "Phoenix", "PHOENIX"));
+        assert!(accepted(
+            Comparison::CaseInsensitive,
+            "Phoenix",
+            " PHOENIX "
+        ));
+        assert!(!accepted(Comparison::CaseInsensitive, "Phoenix", "Pheonix"));
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let numeric = Comparison::Numeric {
+            absolute: 1e-3,
+            relative: 0.0,
+        };
+        assert!(!accepted(Comparison::Exact, "42", "42.0"));
+        assert!(accepted(numeric, "42", "42.0"));
+        assert!(accepted(numeric, "42", "4.2e1"));
+        assert!(accepted(numeric, "3.14159", "3.1416"));
+        assert!(!accepted(numeric, "3.14159", "3.15"));
+        assert!(!accepted(numeric, "42", "forty-two"));
+        assert!(accepted(numeric, "forty-two", "forty-two"));
+    }
+}
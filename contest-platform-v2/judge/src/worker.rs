@@ -0,0 +1,460 @@
+//! Judge workers in separate processes (possibly on other machines), which take jobs from the
+//! server's queue over TCP.
+//!
+//! Messages are sent as lines of JSON. A worker opens a connection for each job it can judge at a
+//! time, and starts by sending [`WorkerMessage::Hello`] with the worker token. The server then
+//! sends it a [`Job`] whenever one is available, and the worker sends back every message about the
+//! job up to the final `Done` or `Error`, after which it is ready for the next one. Workers also
+//! send a heartbeat every [`HEARTBEAT_INTERVAL`], and the server gives up on a worker that misses
+//! a few in a row (or disconnects), putting the job it was judging back in the queue.
+//!
+//! Each job carries the [version](crate::registry::Loaded::version) of its contest. A worker that
+//! doesn't have that version reloads its contests, and if it still doesn't, sends
+//! [`WorkerMessage::Stale`] instead of judging the job. The server then puts the job back in the
+//! queue (moving it on to the current version if its own was replaced) and disconnects, so that
+//! the job goes to another worker while this one reconnects.
+
+use std::{
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    fs,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{
+    constant_time_eq,
+    queue::{self, Queued},
+    registry::{self, Loaded},
+    submit::{self, Job, Message},
+};
+
+/// Port that the server accepts workers on
+pub const PORT: u16 = 8129;
+
+/// How often workers send heartbeats
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the server waits for a message from a worker before giving up on it
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a worker waits before reconnecting after losing its connection to the server
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Directory that workers judge jobs in (relative to their working directory)
+const JOBS_DIR: &str = "jobs";
+
+/// Directory that workers load the contests from (relative to their working directory)
+pub const CONTESTS_DIR: &str = "contests";
+
+/// A message from a worker to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum WorkerMessage {
+    /// Authenticates the worker (first message only)
+    Hello { token: String },
+    /// Shows that the worker is still alive
+    Heartbeat,
+    /// A message about the job being judged
+    Message { message: Message },
+    /// The worker doesn't have the version of the contest that the job needs, so it can't judge it
+    Stale,
+}
+
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("connection closed")]
+    Closed,
+    #[error("no heartbeat for {HEARTBEAT_TIMEOUT:?}")]
+    Timeout,
+    #[error("invalid worker token")]
+    Unauthorized,
+    #[error("unexpected message: {0:?}")]
+    Unexpected(WorkerMessage),
+    #[error("worker has a different version of contest {0}")]
+    Stale(String),
+}
+
+/// Accepts connections from workers, handing each of them jobs from the queue
+pub async fn serve(listener: TcpListener, token: String) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::error!("failed to accept worker connection: {e}");
+                continue;
+            }
+        };
+
+        let token = token.clone();
+        tokio::spawn(async move {
+            tracing::info!("worker {addr} connected");
+            if let Err(e) = coordinate(stream, &token).await {
+                tracing::warn!("worker {addr} disconnected: {e}");
+            }
+        });
+    }
+}
+
+async fn coordinate(stream: TcpStream, token: &str) -> Result<(), WorkerError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    match receive_update(&mut lines).await? {
+        WorkerMessage::Hello { token: given }
+            if constant_time_eq(given.as_bytes(), token.as_bytes()) => {}
+        WorkerMessage::Hello { .. } => return Err(WorkerError::Unauthorized),
+        message => return Err(WorkerError::Unexpected(message)),
+    }
+
+    loop {
+        // the worker still has to send heartbeats while it waits for a job
+        let queued = loop {
+            tokio::select! {
+                queued = queue::take() => break queued,
+                message = receive_update(&mut lines) => match message? {
+                    WorkerMessage::Heartbeat => {}
+                    message => return Err(WorkerError::Unexpected(message)),
+                },
+            }
+        };
+
+        queued.start().await;
+        if let Err(e) = relay(&queued, &mut writer, &mut lines).await {
+            let queued = match e {
+                WorkerError::Stale(_) => refresh(queued).await,
+                _ => Some(queued),
+            };
+            if let Some(queued) = queued {
+                queue::requeue(queued).await;
+            }
+            return Err(e);
+        }
+    }
+}
+
+/// Moves a job on to the current version of its contest if the server has reloaded it since the
+/// job was submitted, as workers only load the current version
+///
+/// Returns `None` (after storing an error) if the contest no longer exists.
+async fn refresh(mut queued: Queued) -> Option<Queued> {
+    let Some(contest) = registry::contests().get(&queued.job.contest).cloned() else {
+        let reason = format!("contest {} no longer exists", queued.job.contest);
+        queued.entry.push(&Message::Error { reason }).await;
+        return None;
+    };
+
+    if contest.version() != queued.job.version {
+        tracing::info!(
+            "moving submission {} on to the current version of contest {}",
+            queued.job.id,
+            queued.job.contest
+        );
+        queued.job.version = contest.version().to_owned();
+        queued.contest = contest;
+    }
+
+    Some(queued)
+}
+
+/// Sends a job to a worker, storing the messages about it until the final one
+async fn relay(
+    queued: &Queued,
+    writer: &mut OwnedWriteHalf,
+    lines: &mut Lines<impl AsyncBufRead + Unpin>,
+) -> Result<(), WorkerError> {
+    send(writer, &queued.job).await?;
+
+    loop {
+        match receive_update(lines).await? {
+            WorkerMessage::Heartbeat => {}
+            WorkerMessage::Message { message } => {
+                queued.entry.push(&message).await;
+                if matches!(message, Message::Done { .. } | Message::Error { .. }) {
+                    return Ok(());
+                }
+            }
+            WorkerMessage::Stale => return Err(WorkerError::Stale(queued.job.contest.clone())),
+            message => return Err(WorkerError::Unexpected(message)),
+        }
+    }
+}
+
+/// Judges jobs from the server at `addr` one at a time, reconnecting whenever the connection is
+/// lost
+pub async fn work(addr: &str, token: &str) {
+    loop {
+        if let Err(e) = connect(addr, token).await {
+            tracing::warn!("lost connection to {addr}: {e}");
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(addr: &str, token: &str) -> Result<(), WorkerError> {
+    let stream = TcpStream::connect(addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    let lines = BufReader::new(reader).lines();
+
+    let hello = WorkerMessage::Hello {
+        token: token.to_owned(),
+    };
+    send(&mut writer, &hello).await?;
+    tracing::info!("connected to {addr}");
+
+    let (tx, rx) = mpsc::channel(16);
+    Err(tokio::select! {
+        e = send_updates(writer, rx) => e,
+        e = judge_jobs(lines, tx) => e,
+    })
+}
+
+/// Sends the messages from `rx` to the server along with regular heartbeats, until sending fails
+async fn send_updates(
+    mut writer: OwnedWriteHalf,
+    mut rx: mpsc::Receiver<WorkerMessage>,
+) -> WorkerError {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        let message = tokio::select! {
+            _ = heartbeat.tick() => WorkerMessage::Heartbeat,
+            Some(message) = rx.recv() => message,
+        };
+
+        if let Err(e) = send(&mut writer, &message).await {
+            return e;
+        }
+    }
+}
+
+/// Judges the jobs sent by the server, until receiving fails
+async fn judge_jobs(
+    mut lines: Lines<impl AsyncBufRead + Unpin>,
+    tx: mpsc::Sender<WorkerMessage>,
+) -> WorkerError {
+    loop {
+        let job: Job = match receive(&mut lines).await {
+            Ok(job) => job,
+            Err(e) => return e,
+        };
+
+        tracing::info!("judging submission {}", job.id);
+        tokio::select! {
+            () = judge(job, &tx) => {}
+            // the server sends nothing else until the job is done, so this only finishes (which
+            // cancels the job) once the connection is lost
+            Err(e) = receive::<Job>(&mut lines) => return e,
+        }
+    }
+}
+
+/// Judges a job in a scratch directory with the version of the contest it was submitted with,
+/// sending the messages about it to the server as they come
+async fn judge(job: Job, tx: &mpsc::Sender<WorkerMessage>) {
+    let update = |message| WorkerMessage::Message { message };
+
+    let Some(contest) = contest(&job).await else {
+        tracing::warn!("no version {} of contest {}", job.version, job.contest);
+        tx.send(WorkerMessage::Stale).await.ok();
+        return;
+    };
+
+    let dir = Path::new(JOBS_DIR).join(job.id.to_string());
+    if let Err(e) = fs::create_dir_all(&dir).await {
+        let reason = format!("failed to create job directory: {e}");
+        tx.send(update(Message::Error { reason })).await.ok();
+        return;
+    }
+
+    let (mut messages, judging) = submit::spawn(job, contest, dir.clone());
+    let _scratch = Scratch {
+        dir,
+        judging: Some(judging),
+    };
+    while let Some(message) = messages.recv().await {
+        tx.send(update(message)).await.ok();
+    }
+}
+
+/// A job's scratch directory, which is removed once the judging in it has finished
+///
+/// This also happens if the job is dropped part way through (as the connection to the server was
+/// lost), in which case dropping the receiver of its messages has already cancelled the judging.
+struct Scratch {
+    dir: PathBuf,
+    judging: Option<JoinHandle<()>>,
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let (dir, judging) = (mem::take(&mut self.dir), self.judging.take());
+        tokio::spawn(async move {
+            if let Some(judging) = judging {
+                judging.await.ok();
+            }
+            if let Err(e) = fs::remove_dir_all(&dir).await {
+                tracing::warn!("failed to remove {}: {e}", dir.display());
+            }
+        });
+    }
+}
+
+/// This worker's version of the contest for a job, reloading the contests if it isn't the one
+/// that the job needs (e.g. as the server reloaded them)
+async fn contest(job: &Job) -> Option<Arc<Loaded>> {
+    let current = || {
+        registry::contests()
+            .get(&job.contest)
+            .filter(|contest| contest.version() == job.version)
+            .cloned()
+    };
+
+    if let Some(contest) = current() {
+        return Some(contest);
+    }
+
+    if let Err(e) = registry::reload(Path::new(CONTESTS_DIR)).await {
+        tracing::error!("failed to reload contests: {e:?}");
+    }
+    current()
+}
+
+async fn send(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &impl Serialize,
+) -> Result<(), WorkerError> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+async fn receive<T: DeserializeOwned>(
+    lines: &mut Lines<impl AsyncBufRead + Unpin>,
+) -> Result<T, WorkerError> {
+    let line = lines.next_line().await?.ok_or(WorkerError::Closed)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Receives a message from a worker, which must arrive before the heartbeat timeout
+///
+/// This is cancel safe, as no part of a line is consumed until all of it has arrived.
+async fn receive_update(
+    lines: &mut Lines<impl AsyncBufRead + Unpin>,
+) -> Result<WorkerMessage, WorkerError> {
+    tokio::time::timeout(HEARTBEAT_TIMEOUT, receive(lines))
+        .await
+        .map_err(|_| WorkerError::Timeout)?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tokio::time::{sleep, timeout, Instant};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{contest::Contest, CONTESTS};
+
+    /// How long runs in the contest's only language sleep for (seconds), which is far longer than
+    /// the test waits for them
+    const SLEEP: &str = "7.25";
+
+    const CONTEST: &str = r#"{
+        "name": "Worker",
+        "duration": 3600,
+        "submission-cooldown": 0,
+        "page": "",
+        "tasks": [{
+            "name": "Sleep",
+            "difficulty": "Easy",
+            "answer": null,
+            "page": "",
+            "subtasks": [{ "tests": [{ "input": "", "output": "" }], "score": null }],
+            "checker": null
+        }],
+        "scoring": { "answer-score": 0, "test-score": 1, "subtask-score": 0 },
+        "judge": {
+            "skip-count": 1,
+            "resource-limits": { "cpu": 1, "cpu-tolerance": 0, "memory": 256000000,
+                "memory-tolerance": 0, "wall-time": 30 },
+            "languages": [{ "name": "Sleep", "filename": "sleep.txt", "run": ["sleep", "7.25"] }]
+        }
+    }"#;
+
+    /// Whether a process is running `sleep` for [`SLEEP`] seconds
+    fn sleeping() -> bool {
+        let cmdline = format!("sleep\0{SLEEP}\0");
+        std::fs::read_dir("/proc").unwrap().flatten().any(|entry| {
+            std::fs::read(entry.path().join("cmdline")).is_ok_and(|c| c == cmdline.as_bytes())
+        })
+    }
+
+    async fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    #[tokio::test]
+    async fn lost_connection_cancels_job() {
+        let contest = Contest::load(CONTEST).unwrap();
+        let loaded = Loaded::new("worker-test", contest).await.unwrap();
+        let job = Job {
+            id: Uuid::new_v4(),
+            contest: "worker-test".to_owned(),
+            task: 1,
+            language: "Sleep".to_owned(),
+            code: String::new(),
+            version: loaded.version().to_owned(),
+        };
+        let contests = BTreeMap::from([("worker-test".to_owned(), loaded)]);
+        CONTESTS.store(Arc::new(contests.into_iter().collect()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let worker = tokio::spawn(async move { connect(&addr, "token").await });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let hello: WorkerMessage = receive(&mut lines).await.unwrap();
+        assert!(matches!(hello, WorkerMessage::Hello { token } if token == "token"));
+        send(&mut writer, &job).await.unwrap();
+
+        let dir = Path::new(JOBS_DIR).join(job.id.to_string());
+        assert!(wait_until(sleeping).await, "the run never started");
+        assert!(dir.is_dir());
+
+        drop((lines, writer));
+        let result = timeout(Duration::from_secs(5), worker).await.unwrap();
+        assert!(matches!(result.unwrap(), Err(WorkerError::Closed)));
+
+        assert!(wait_until(|| !sleeping()).await, "the run is still going");
+        assert!(
+            wait_until(|| !dir.exists()).await,
+            "the job directory is left over"
+        );
+        std::fs::remove_dir(JOBS_DIR).ok();
+    }
+}
@@ -7,10 +7,23 @@
 
 export type Message =
   | {
+      id: string;
+      type: "Submitted";
+      [k: string]: unknown;
+    }
+  | {
+      /**
+       * Position in the queue (starting from 1)
+       */
+      position: number;
       tests: number;
       type: "Queued";
       [k: string]: unknown;
     }
+  | {
+      type: "Requeued";
+      [k: string]: unknown;
+    }
   | {
       type: "Compiling";
       [k: string]: unknown;
@@ -33,6 +46,7 @@ export type Message =
     }
   | {
       report: Report;
+      score: Score;
       type: "Done";
       [k: string]: unknown;
     }
@@ -81,3 +95,25 @@ export interface Duration {
   secs: number;
   [k: string]: unknown;
 }
+/**
+ * Points earned by a submission, laid out like its [`Report`]
+ */
+export interface Score {
+  /**
+   * Most points the task can earn
+   */
+  max: number;
+  /**
+   * Points for each subtask, including its tests
+   */
+  subtasks: number[];
+  /**
+   * Points for the task, including its subtasks
+   */
+  task: number;
+  /**
+   * Points for each test
+   */
+  tests: number[][];
+  [k: string]: unknown;
+}
@@ -0,0 +1,60 @@
+import { EventSourceParserStream } from 'eventsource-parser/stream';
+import type { Message } from '$lib/judge/schema';
+
+export const JUDGE_URL = 'http://judge:8128';
+
+/** Attempts at reconnecting to the judge in a row before giving up on a submission */
+const MAX_RECONNECTS = 5;
+
+export interface JudgeEvent {
+	/** Position in the submission's event log (unset for updates that the judge doesn't store) */
+	id?: string;
+	data: string;
+	message: Message;
+}
+
+/**
+ * Follows the messages of a submission until judging finishes, starting from the response to
+ * submitting it. Whenever the connection drops, it reconnects to the submission's event log and
+ * resumes after the last message received.
+ */
+export async function* followSubmission(
+	id: string,
+	response: Response
+): AsyncGenerator<JudgeEvent, void, undefined> {
+	let current: Response | undefined = response;
+	let lastEventId: string | undefined;
+	let reconnects = 0;
+
+	while (true) {
+		try {
+			current ??= await fetch(`${JUDGE_URL}/submissions/${id}/events`, {
+				headers: lastEventId !== undefined ? { 'Last-Event-ID': lastEventId } : {}
+			});
+			if (!current.ok) throw new Error(`judge responded with ${current.status}`);
+
+			const reader = current
+				.body!.pipeThrough(new TextDecoderStream())
+				.pipeThrough(new EventSourceParserStream())
+				.getReader();
+
+			while (true) {
+				const { value, done } = await reader.read();
+				if (done) break;
+
+				const message: Message = JSON.parse(value.data);
+				if (value.id !== undefined) lastEventId = value.id;
+				reconnects = 0;
+				yield { id: value.id, data: value.data, message };
+
+				if (message.type === 'Done' || message.type === 'Error') return;
+			}
+		} catch (e) {
+			console.error(`lost connection to judge for submission ${id}:`, e);
+		}
+
+		if (++reconnects > MAX_RECONNECTS) throw new Error(`gave up on submission ${id}`);
+		current = undefined;
+		await new Promise((resolve) => setTimeout(resolve, 500 * 2 ** reconnects));
+	}
+}
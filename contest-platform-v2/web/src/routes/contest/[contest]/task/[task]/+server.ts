@@ -1,9 +1,9 @@
 import { error } from '@sveltejs/kit';
-import { createParser } from 'eventsource-parser';
 import { db } from '$lib/server/db';
 import { contests, submissions, tests } from '$lib/server/db/schema';
 import { eq } from 'drizzle-orm';
 import { getContest } from '$lib/server/contest/load';
+import { followSubmission, JUDGE_URL } from '$lib/server/judge';
 import type { Message, ResourceUsage } from '$lib/judge/schema';
 import type { RequestHandler } from './$types';
 
@@ -22,81 +22,115 @@ export const POST: RequestHandler = async ({ fetch, request, params, locals }) =
 	const formData = await request.formData();
 	formData.set('contest', params.contest);
 	formData.set('task', params.task);
+	formData.set('user', locals.user.id);
+	// submissions are only accepted while the contest is running
+	formData.set('priority', 'contest');
 
-	const response = await fetch('http://judge:8128', {
+	const response = await fetch(JUDGE_URL, {
 		method: 'POST',
 		body: formData
 	});
 
 	if (!response.ok) return response;
 
-	const reader = response.body!.getReader();
-	const decoder = new TextDecoder();
+	const id = response.headers.get('X-Submission-Id');
+	if (!id) error(502, 'judge did not return a submission ID');
 
 	const codeFile = <File>formData.get('code')!;
 	const code = await codeFile.text();
 
-	const parser = createParser({
-		onEvent: async (event) => {
-			const message: Message = JSON.parse(event.data);
-			if (message.type === 'Done') {
-				const report = message.report;
-
-				const subtaskScore = report.subtasks
-					.map((verdict) => (verdict === 'Accepted' ? contestData.scoring['subtask-score'] : 0))
-					.reduce((acc, subtaskScore) => acc + subtaskScore, 0);
-				const testScore =
-					report.tests.flatMap((subtask) => subtask).filter((test) => test.verdict === 'Accepted')
-						.length * contestData.scoring['test-score'];
-				const score = subtaskScore + testScore;
-
-				const submission = await db.insert(submissions).values({
-					userId: locals.user!.id,
-					contestId: contest.id,
-					task: parseInt(params.task),
-					code,
-					language: formData.get('language')!.toString(),
-					score,
-					verdict: report.task
-				});
-
-				const testValues = report.tests.flatMap((tests, subtask) =>
-					tests.map((test, index) => ({
-						submissionId: Number(submission.lastInsertRowid),
-						subtask: subtask + 1,
-						index: index + 1,
-						runtime: durationToMilliseconds(test.resource_usage),
-						memory: test.resource_usage.memory,
-						verdict: test.verdict
-					}))
-				);
-
-				await db.insert(tests).values(testValues);
-			}
+	const userId = locals.user.id;
+	const store = async (message: Extract<Message, { type: 'Done' }>) => {
+		const report = message.report;
+
+		const submission = await db.insert(submissions).values({
+			userId,
+			contestId: contest.id,
+			task: parseInt(params.task),
+			code,
+			language: formData.get('language')!.toString(),
+			// the judge scores according to the contest's scoring model, possibly with fractions
+			score: Math.round(message.score.task),
+			verdict: report.task
+		});
+
+		const testValues = report.tests.flatMap((tests, subtask) =>
+			tests.map((test, index) => ({
+				submissionId: Number(submission.lastInsertRowid),
+				subtask: subtask + 1,
+				index: index + 1,
+				runtime: durationToMilliseconds(test.resource_usage),
+				memory: test.resource_usage.memory,
+				verdict: test.verdict
+			}))
+		);
+
+		await db.insert(tests).values(testValues);
+	};
+
+	const encoder = new TextEncoder();
+	let client: ReadableStreamDefaultController<Uint8Array> | undefined;
+	const stream = new ReadableStream<Uint8Array>({
+		start(controller) {
+			client = controller;
+		},
+		cancel() {
+			client = undefined;
 		}
 	});
 
-	const stream = new ReadableStream({
-		async pull(controller) {
-			const { done, value } = await reader.read();
-			if (done) {
-				controller.close();
-				return;
+	const send = (chunk: string) => {
+		try {
+			client?.enqueue(encoder.encode(chunk));
+		} catch {
+			// the client went away
+			client = undefined;
+		}
+	};
+
+	// judging is followed to the end even if the client goes away, so that the result is stored
+	(async () => {
+		try {
+			for await (const event of followSubmission(id, response)) {
+				send(`${event.id !== undefined ? `id: ${event.id}\n` : ''}data: ${event.data}\n\n`);
+				if (event.message.type === 'Done') await store(event.message);
+			}
+		} catch (e) {
+			console.error(e);
+		} finally {
+			try {
+				client?.close();
+			} catch {
+				// the client went away
 			}
-
-			const chunk = decoder.decode(value, { stream: true });
-			parser.feed(chunk);
-			controller.enqueue(value);
 		}
-	});
+	})();
 
 	return new Response(stream, {
 		headers: {
-			'Content-Type': 'text/event-stream'
+			'Content-Type': 'text/event-stream',
+			'X-Submission-Id': id
 		}
 	});
 };
 
+/** Resumes following one of the user's submissions after the `Last-Event-ID` header */
+export const GET: RequestHandler = async ({ fetch, request, url, locals }) => {
+	if (!locals.user) error(401);
+
+	const id = url.searchParams.get('submission');
+	if (!id) error(400, 'submission not given');
+
+	const record = await fetch(`${JUDGE_URL}/submissions/${encodeURIComponent(id)}`);
+	if (!record.ok || (await record.json()).user !== locals.user.id)
+		error(404, 'submission not found');
+
+	const lastEventId = request.headers.get('Last-Event-ID');
+	return fetch(`${JUDGE_URL}/submissions/${encodeURIComponent(id)}/events`, {
+		headers: lastEventId ? { 'Last-Event-ID': lastEventId } : {}
+	});
+};
+
 function durationToMilliseconds(resourceUsage: ResourceUsage): number {
 	return (
 		resourceUsage['sys-time'].secs * 1000 +